@@ -0,0 +1,52 @@
+//! Measures the throughput of crab and the system cat for each flag set on generated inputs.
+//! Run with `cargo bench`; criterion reports regressions against the previous run.
+
+#[path = "../src/bin/crab-bench/inputs.rs"]
+mod inputs;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use inputs::{Input, FLAG_SETS};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// The sizes of the generated inputs. Kept small, since every measurement spawns a process.
+const SIZES: [usize; 2] = [1 << 20, 16 << 20];
+
+/// Runs `binary` with `flags` on `path`, discarding the output.
+fn run(binary: &str, flags: &[&str], path: &Path) {
+    let status = Command::new(binary)
+        .args(flags)
+        .arg(path)
+        .stdout(Stdio::null())
+        .status()
+        .expect("cannot run the benchmarked binary");
+    assert!(status.success());
+}
+
+fn throughput(c: &mut Criterion) {
+    let dir = std::env::temp_dir().join(format!("crab-criterion-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    for size in SIZES {
+        for input in Input::ALL {
+            let path = dir.join(format!("{}-{}", input.name(), size));
+            input.generate(&path, size).unwrap();
+            let mut group = c.benchmark_group(format!("{}/{}KiB", input.name(), size >> 10));
+            group.throughput(Throughput::Bytes(size as u64)).sample_size(10);
+            for flags in FLAG_SETS {
+                let name = if flags.is_empty() { String::from("plain") } else { flags.join(" ") };
+                group.bench_with_input(BenchmarkId::new("crab", &name), flags, |b, flags| {
+                    b.iter(|| run(env!("CARGO_BIN_EXE_crab"), flags, &path))
+                });
+                group.bench_with_input(BenchmarkId::new("cat", &name), flags, |b, flags| {
+                    b.iter(|| run("cat", flags, &path))
+                });
+            }
+            group.finish();
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+    let _ = std::fs::remove_dir(&dir);
+}
+
+criterion_group!(benches, throughput);
+criterion_main!(benches);
@@ -0,0 +1,158 @@
+//! Feeds the same input to the formatting state machine in one piece and split at arbitrary
+//! chunk boundaries, for every combination of flags, and checks that the output does not depend
+//! on the chunking and matches a simple line by line reference implementation.
+//!
+//! Run with `cargo fuzz run chunking` from the repository root.
+
+#![no_main]
+
+#[path = "../../src/mapping.rs"]
+mod mapping;
+#[path = "../../src/format.rs"]
+mod format;
+
+use arbitrary::Arbitrary;
+use format::{FormatOptions, FormatState, TabStops};
+use mapping::Glyphs;
+use libfuzzer_sys::fuzz_target;
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    /// The bytes to format.
+    data: Vec<u8>,
+    /// The lengths of the chunks the data is split into, cycled until the data runs out.
+    chunks: Vec<u8>,
+    /// The flags the reference implementation does not cover, combined with every other one.
+    extra: u8,
+}
+
+/// Returns the flags encoded in `bits` and `extra`. The flags of `extra` expand tabs, unexpand
+/// tabs, draw blanks with either set of glyphs and highlight trailing blanks, which the
+/// reference implementation does not cover.
+fn options(bits: u8, extra: u8) -> FormatOptions {
+    let number_lines = bits & 16 != 0 || bits & 32 != 0;
+    FormatOptions {
+        show_non_printing: bits & 1 != 0,
+        show_ends: bits & 2 != 0,
+        show_tabs: bits & 4 != 0,
+        squeeze_blank: bits & 8 != 0,
+        number_lines,
+        number_non_blank: bits & 32 != 0,
+        expand_tabs: (extra & 1 != 0).then_some(TabStops::Every(4)),
+        unexpand: (extra & 2 != 0).then_some(TabStops::List(&[3, 5, 12])),
+        show_spaces: [None, Some(Glyphs::Unicode), None, Some(Glyphs::Ascii)][(extra as usize >> 2) & 3],
+        highlight_trailing: extra & 16 != 0,
+    }
+}
+
+/// Formats `data` in one call.
+fn whole(options: FormatOptions, data: &[u8]) -> Vec<u8> {
+    let mut state = FormatState::new(options);
+    let mut output = vec![];
+    state.format(data, &mut output).unwrap();
+    state.finish(&mut output).unwrap();
+    output
+}
+
+/// Formats `data` split into chunks of the given lengths. Zero-length chunks are kept, since
+/// empty writes must not change anything either.
+fn chunked(options: FormatOptions, data: &[u8], chunks: &[u8]) -> Vec<u8> {
+    let mut state = FormatState::new(options);
+    let mut output = vec![];
+    let mut rest = data;
+    let mut lengths = chunks.iter().map(|&len| len as usize).cycle();
+    // Chunks that are all empty would never get through the data, so write it in one piece.
+    let stuck = chunks.iter().all(|&len| len == 0);
+    loop {
+        let len = if stuck { rest.len() } else { lengths.next().unwrap() };
+        let (chunk, tail) = rest.split_at(len.min(rest.len()));
+        state.format(chunk, &mut output).unwrap();
+        rest = tail;
+        if rest.is_empty() {
+            break;
+        }
+    }
+    state.finish(&mut output).unwrap();
+    output
+}
+
+/// Formats `data` by skipping over the first chunk and formatting the rest from the resulting
+/// state, the way the threaded pipeline computes the state at each chunk boundary.
+fn skipped(options: FormatOptions, data: &[u8], split: usize) -> Vec<u8> {
+    let (head, tail) = data.split_at(split.min(data.len()));
+    let mut state = FormatState::new(options);
+    let mut output = vec![];
+    state.format(head, &mut output).unwrap();
+    let mut resumed = FormatState::new(options);
+    resumed.skip(head);
+    assert_eq!(resumed.squeezed(), state.squeezed());
+    resumed.format(tail, &mut output).unwrap();
+    resumed.finish(&mut output).unwrap();
+    output
+}
+
+/// Renders a single byte of a line's content the way GNU cat does.
+fn render(options: FormatOptions, byte: u8, output: &mut Vec<u8>) {
+    match byte {
+        b'\t' if options.show_tabs => output.extend_from_slice(b"^I"),
+        b'\t' => output.push(b'\t'),
+        _ if !options.show_non_printing => output.push(byte),
+        0..=31 => output.extend_from_slice(&[b'^', byte + 64]),
+        127 => output.extend_from_slice(b"^?"),
+        128..=159 => output.extend_from_slice(&[b'M', b'-', b'^', byte - 64]),
+        160..=254 => output.extend_from_slice(&[b'M', b'-', byte - 128]),
+        255 => output.extend_from_slice(b"M-^?"),
+        _ => output.push(byte),
+    }
+}
+
+/// A straightforward line by line implementation of GNU cat's formatting.
+fn reference(options: FormatOptions, data: &[u8]) -> Vec<u8> {
+    let mut output = vec![];
+    let mut number = 1;
+    let mut last_blank = false;
+    for line in data.split_inclusive(|&byte| byte == b'\n') {
+        let (content, ended) = match line.strip_suffix(b"\n") {
+            Some(content) => (content, true),
+            None => (line, false),
+        };
+        let blank = content.is_empty();
+        if blank && last_blank && options.squeeze_blank {
+            continue;
+        }
+        last_blank = blank;
+        if options.number_lines && !(blank && options.number_non_blank) {
+            output.extend_from_slice(format!("{:>6}\t", number).as_bytes());
+            number += 1;
+        }
+        // With -E but without -v, a carriage return ending a line is shown as ^M.
+        let crlf = ended && options.show_ends && !options.show_non_printing && content.last() == Some(&b'\r');
+        let shown = if crlf { &content[..content.len() - 1] } else { content };
+        for &byte in shown {
+            render(options, byte, &mut output);
+        }
+        if crlf {
+            output.extend_from_slice(b"^M");
+        }
+        if ended {
+            output.extend_from_slice(if options.show_ends { b"$\n" } else { b"\n" });
+        }
+    }
+    output
+}
+
+fuzz_target!(|input: Input| {
+    for (bits, extra) in (0..64).flat_map(|bits| [(bits, 0), (bits, input.extra & 31)]) {
+        let options = options(bits, extra);
+        let expected = if extra == 0 { reference(options, &input.data) } else { whole(options, &input.data) };
+        assert_eq!(whole(options, &input.data), expected, "{:?}", options);
+        if !input.chunks.is_empty() {
+            assert_eq!(chunked(options, &input.data, &input.chunks), expected, "{:?}", options);
+            // The pipeline formats highlighted blanks on one thread, without skipping.
+            if !options.highlight_trailing {
+                let split = input.chunks.iter().map(|&len| len as usize).sum();
+                assert_eq!(skipped(options, &input.data, split), expected, "{:?}", options);
+            }
+        }
+    }
+});
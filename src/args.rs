@@ -1,14 +1,23 @@
-use clap::Parser;
+use atty::Stream;
+use clap::{AppSettings, ArgEnum, Parser};
 use lazy_static::lazy_static;
+use crate::checksum::Algorithm;
+use crate::codec::{CodecState, Encoding};
+use crate::errors::{ErrorFormat, OnError};
+use crate::format::{FormatOptions, TabStops};
+use crate::json::OutputFormat;
+use crate::mapping::Glyphs;
+use crate::wrap::{terminal_width, Overflow, WrapState};
 
 #[derive(Parser)]
 #[clap(name = "crab 🦀")]
 #[clap(version)]
 #[clap(author = "CarlosEduardoL")]
 #[clap(about = "Rust cat copy", long_about = None)]
+#[clap(setting = AppSettings::AllArgsOverrideSelf)]
 struct CrabArgs {
     #[clap(value_name = "FILES")]
-    /// files to be concatenated, when FILE is -, read standard input.
+    /// files to be concatenated, when FILE is -, read standard input; ARCHIVE!MEMBER reads a member of a tar, tar.gz or zip archive, unix:PATH the data sent by a Unix domain socket, and http(s)://URL a download.
     files: Vec<String>,
     #[clap(short, long = "number")]
     /// add A number on the line start
@@ -37,8 +46,195 @@ struct CrabArgs {
     #[clap(short = 'v', long = "show-nonprinting")]
     /// use ^ and M- notation, except for LFD and TAB
     show_non_printing: bool,
+    #[clap(long = "files-from", value_name = "FILE")]
+    /// read the names of the files to concatenate from FILE, one per line; when FILE is -, read standard input
+    files_from: Option<String>,
+    #[clap(short = '0', long = "null", requires = "files-from")]
+    /// names in the --files-from list are terminated by NUL instead of newline
+    null: bool,
+    #[clap(long, value_name = "N", default_value = "1")]
+    /// format the output on N threads, useful with -n, -v or -E on large inputs
+    threads: usize,
+    #[clap(long = "output-format", arg_enum, value_name = "FORMAT", default_value = "text")]
+    /// render the output as text, or describe every line as a JSON object, in one array (json) or one per line (jsonl)
+    output_format: OutputFormat,
+    #[clap(long = "error-format", arg_enum, value_name = "FORMAT", default_value = "text")]
+    /// report errors as text messages, or as JSON objects with their kind, path, errno and message
+    error_format: ErrorFormat,
+    #[clap(long = "on-error", arg_enum, value_name = "POLICY")]
+    /// when an input cannot be read: report it and continue (the default), report it and stop (abort), or skip it silently (ignore); failures are summarized at the end
+    on_error: Option<OnError>,
+    #[clap(long = "missing-ok")]
+    /// treat inputs that do not exist as empty instead of failing
+    missing_ok: bool,
+    #[clap(short, long, value_name = "FILE")]
+    /// write the output to FILE instead of standard output
+    output: Option<String>,
+    #[clap(long, requires = "output", conflicts_with = "atomic")]
+    /// append to the --output file instead of truncating it
+    append: bool,
+    #[clap(long, requires = "output")]
+    /// write the --output file through a temporary file renamed over it at the end, so it is never seen half-written
+    atomic: bool,
+    #[clap(long, value_name = "[FLAGS:]FILE", multiple_occurrences = true, allow_hyphen_values = true, parse(try_from_str = parse_tee))]
+    /// also write the output to FILE, rendered with its own short FLAGS if given, e.g. -n:numbered.txt, or -:plain.txt for the bytes as they are
+    tee: Vec<Tee>,
+    #[clap(long)]
+    /// list the members of the tar and zip archives given as FILES instead of concatenating them
+    list: bool,
+    #[clap(long = "fifo-timeout", value_name = "SECONDS", default_value = "10", parse(try_from_str = parse_timeout))]
+    /// report a FIFO that no writer opens within SECONDS instead of waiting for one; 0 waits forever
+    fifo_timeout: f64,
+    #[clap(long = "http-timeout", value_name = "SECONDS", default_value = "30", parse(try_from_str = parse_timeout))]
+    /// give up on a URL whose server does not connect, answer or send more data within SECONDS; 0 waits forever
+    http_timeout: f64,
+    #[clap(long, arg_enum, value_name = "ENCODING", conflicts_with = "decode")]
+    /// encode the concatenated input as base64, base32, hex or url (percent-encoding)
+    encode: Option<Encoding>,
+    #[clap(long, arg_enum, value_name = "ENCODING")]
+    /// decode the concatenated input from base64, base32, hex or url, ignoring whitespace except in url
+    decode: Option<Encoding>,
+    #[clap(long = "encode-width", value_name = "COLS", requires = "encode")]
+    /// break --encode output into lines of COLS characters (76 by default), 0 for a single line; does not apply to url
+    encode_width: Option<usize>,
+    #[clap(long, arg_enum, value_name = "ALGORITHM")]
+    /// print the sha256, blake3 or crc32 checksum of every input and of the output, like sha256sum, to standard error
+    checksum: Option<Algorithm>,
+    #[clap(long = "checksum-file", value_name = "FILE", requires = "checksum")]
+    /// write the --checksum lines to FILE instead of standard error
+    checksum_file: Option<String>,
+    #[clap(long)]
+    /// print a table of the lines, words, bytes, longest line, non-printing bytes, tabs, CRs, squeezed empty lines and invalid UTF-8 sequences of every input to standard error
+    stats: bool,
+    #[clap(long = "expand-tabs", value_name = "STOPS", min_values = 0, require_equals = true, default_missing_value = "8", conflicts_with = "unexpand", parse(try_from_str = parse_tab_stops))]
+    /// convert TABs to spaces up to the next tab stop, every 8 columns or every N with =N, or at the columns of a list such as =4,8,12; does not apply with -T
+    expand_tabs: Option<TabStops>,
+    #[clap(long, value_name = "STOPS", min_values = 0, require_equals = true, default_missing_value = "8", parse(try_from_str = parse_tab_stops))]
+    /// convert runs of two or more spaces that reach a tab stop to TABs, with the tab stops of --expand-tabs; does not apply with -T
+    unexpand: Option<TabStops>,
+    #[clap(long = "show-spaces", value_name = "GLYPHS", min_values = 0, require_equals = true, default_missing_value = "unicode", parse(try_from_str = parse_glyphs))]
+    /// draw spaces as ·, TABs as → and no-break spaces as ␣, or as ., > and + with =ascii
+    show_spaces: Option<Glyphs>,
+    #[clap(long = "highlight-trailing")]
+    /// color the spaces, TABs and no-break spaces at the end of every line with a red background
+    highlight_trailing: bool,
+    #[clap(long, arg_enum, value_name = "WHEN", default_value = "auto")]
+    /// use colors always, never, or only when standard output is a terminal and there is no --output (auto)
+    color: Color,
+    #[clap(long, value_name = "WIDTH", min_values = 0, require_equals = true, default_missing_value = "auto", conflicts_with = "truncate", parse(try_from_str = parse_width))]
+    /// wrap the lines of the output that are wider than WIDTH columns, or than the terminal with auto (the default)
+    wrap: Option<Width>,
+    #[clap(long, value_name = "WIDTH", parse(try_from_str = parse_width))]
+    /// cut the lines of the output after WIDTH columns, or after the width of the terminal with auto
+    truncate: Option<Width>,
 }
 
+/// When the output is colored, as `--color` says.
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Color {
+    /// Only if the output is a terminal.
+    Auto,
+    /// Always.
+    Always,
+    /// Never.
+    Never,
+}
+
+/// A width given to `--wrap` or `--truncate`.
+#[derive(Clone, Copy)]
+pub enum Width {
+    /// The width of the terminal.
+    Auto,
+    /// This many columns.
+    Columns(usize),
+}
+
+/// An extra output requested with `--tee`.
+pub struct Tee {
+    /// The file to write to.
+    pub path: String,
+    /// The formatting flags of this output, or `None` to render it like the main output.
+    pub options: Option<FormatOptions>,
+}
+
+/// Parses a `--tee` value: a file name, optionally preceded by short flags and a colon, such
+/// as `-nE:out.txt`. A lone `-` before the colon means no formatting at all.
+fn parse_tee(value: &str) -> Result<Tee, String> {
+    let (flags, path) = match value.split_once(':') {
+        Some((flags, path)) if flags.starts_with('-') => (flags, path),
+        _ => return Ok(Tee { path: value.to_string(), options: None }),
+    };
+    if path.is_empty() {
+        return Err(String::from("missing file name after the flags"));
+    }
+    let mut options = FormatOptions::default();
+    for flag in flags[1..].chars() {
+        match flag {
+            'n' => options.number_lines = true,
+            'b' => options.number_non_blank = true,
+            's' => options.squeeze_blank = true,
+            'v' => options.show_non_printing = true,
+            'E' => options.show_ends = true,
+            'T' => options.show_tabs = true,
+            'A' => (options.show_non_printing, options.show_ends, options.show_tabs) = (true, true, true),
+            'e' => (options.show_non_printing, options.show_ends) = (true, true),
+            't' => (options.show_non_printing, options.show_tabs) = (true, true),
+            _ => return Err(format!("unknown flag '{}', expected some of nbsvETAet", flag)),
+        }
+    }
+    options.number_lines |= options.number_non_blank;
+    Ok(Tee { path: path.to_string(), options: Some(options) })
+}
+
+/// Parses a `--fifo-timeout` or `--http-timeout` value, a non-negative number of seconds.
+fn parse_timeout(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(seconds) if seconds.is_finite() && seconds >= 0.0 => Ok(seconds),
+        _ => Err(String::from("expected a non-negative number of seconds")),
+    }
+}
+
+/// Parses an `--expand-tabs` or `--unexpand` value: a tab stop every N columns, or the columns
+/// of the tab stops as a strictly increasing list separated by commas.
+fn parse_tab_stops(value: &str) -> Result<TabStops, String> {
+    let stops: Vec<usize> = value
+        .split(',')
+        .map(|stop| stop.trim().parse::<usize>().map_err(|_| format!("invalid tab stop '{}'", stop)))
+        .collect::<Result<_, _>>()?;
+    match stops.as_slice() {
+        [0] => Err(String::from("tab stops must be at least 1 column apart")),
+        [width] => Ok(TabStops::Every(*width)),
+        _ if stops[0] == 0 || stops.windows(2).any(|pair| pair[0] >= pair[1]) => {
+            Err(String::from("tab stops must be positive and in increasing order"))
+        }
+        // The stops are parsed once, for the whole run.
+        _ => Ok(TabStops::List(Box::leak(stops.into_boxed_slice()))),
+    }
+}
+
+/// Parses a `--show-spaces` value, `unicode` or `ascii`.
+fn parse_glyphs(value: &str) -> Result<Glyphs, String> {
+    match value {
+        "unicode" => Ok(Glyphs::Unicode),
+        "ascii" => Ok(Glyphs::Ascii),
+        _ => Err(String::from("expected unicode or ascii")),
+    }
+}
+
+/// Parses a `--wrap` or `--truncate` value, `auto` or a positive number of columns.
+fn parse_width(value: &str) -> Result<Width, String> {
+    match value {
+        "auto" => Ok(Width::Auto),
+        _ => match value.parse::<usize>() {
+            Ok(columns) if columns > 0 => Ok(Width::Columns(columns)),
+            _ => Err(String::from("expected auto or a positive number of columns")),
+        },
+    }
+}
+
+/// The length of the lines of encoded output, the same as GNU base64.
+const ENCODE_WIDTH: usize = 76;
+
 lazy_static! {
     pub static ref ARGS: Args = {
         let mut args = CrabArgs::parse();
@@ -55,6 +251,33 @@ lazy_static! {
             number_lines: args.number_lines,
             squeeze_blank: args.squeeze_blank,
             number_non_blank: args.number_non_blank,
+            files_from: args.files_from,
+            null: args.null,
+            threads: args.threads,
+            output_format: args.output_format,
+            error_format: args.error_format,
+            on_error: args.on_error,
+            missing_ok: args.missing_ok,
+            output: args.output,
+            append: args.append,
+            atomic: args.atomic,
+            tee: args.tee,
+            list: args.list,
+            fifo_timeout: args.fifo_timeout,
+            http_timeout: args.http_timeout,
+            encode: args.encode,
+            decode: args.decode,
+            encode_width: args.encode_width,
+            checksum: args.checksum,
+            checksum_file: args.checksum_file,
+            stats: args.stats,
+            expand_tabs: args.expand_tabs,
+            unexpand: args.unexpand,
+            show_spaces: args.show_spaces,
+            highlight_trailing: args.highlight_trailing,
+            color: args.color,
+            wrap: args.wrap,
+            truncate: args.truncate,
         }
     };
 }
@@ -70,4 +293,84 @@ pub struct Args {
     pub number_lines: bool,
     pub squeeze_blank: bool,
     pub number_non_blank: bool,
-}
\ No newline at end of file
+    pub files_from: Option<String>,
+    pub null: bool,
+    pub threads: usize,
+    pub output_format: OutputFormat,
+    pub error_format: ErrorFormat,
+    pub on_error: Option<OnError>,
+    pub missing_ok: bool,
+    pub output: Option<String>,
+    pub append: bool,
+    pub atomic: bool,
+    pub tee: Vec<Tee>,
+    pub list: bool,
+    pub fifo_timeout: f64,
+    pub http_timeout: f64,
+    pub encode: Option<Encoding>,
+    pub decode: Option<Encoding>,
+    pub encode_width: Option<usize>,
+    pub checksum: Option<Algorithm>,
+    pub checksum_file: Option<String>,
+    pub stats: bool,
+    pub expand_tabs: Option<TabStops>,
+    pub unexpand: Option<TabStops>,
+    pub show_spaces: Option<Glyphs>,
+    pub highlight_trailing: bool,
+    pub color: Color,
+    pub wrap: Option<Width>,
+    pub truncate: Option<Width>,
+}
+
+impl Args {
+    /// Returns the formatting flags.
+    pub fn format(&self) -> FormatOptions {
+        FormatOptions {
+            show_non_printing: self.show_non_printing,
+            show_ends: self.show_ends,
+            show_tabs: self.show_tabs,
+            number_lines: self.number_lines,
+            squeeze_blank: self.squeeze_blank,
+            number_non_blank: self.number_non_blank,
+            expand_tabs: self.expand_tabs,
+            unexpand: self.unexpand,
+            show_spaces: self.show_spaces,
+            highlight_trailing: self.highlight_trailing && self.colored(),
+        }
+    }
+
+    /// Returns whether the output is colored, which with `--color=auto` depends on whether it
+    /// goes to a terminal.
+    fn colored(&self) -> bool {
+        match self.color {
+            Color::Auto => self.output.is_none() && atty::is(Stream::Stdout),
+            Color::Always => true,
+            Color::Never => false,
+        }
+    }
+
+    /// Returns the state converting the concatenated input as `--encode` or `--decode` say,
+    /// if either was given.
+    pub fn codec(&self) -> Option<CodecState> {
+        match (self.encode, self.decode) {
+            (Some(encoding), _) => Some(CodecState::new(encoding, false, self.encode_width.unwrap_or(ENCODE_WIDTH))),
+            (None, Some(encoding)) => Some(CodecState::new(encoding, true, 0)),
+            (None, None) => None,
+        }
+    }
+
+    /// Returns the state fitting the output in a width as `--wrap` or `--truncate` say, if
+    /// either was given. The width of the terminal is only known if there is one.
+    pub fn fit(&self) -> Option<WrapState> {
+        let (width, overflow) = match (self.wrap, self.truncate) {
+            (Some(width), _) => (width, Overflow::Wrap),
+            (None, Some(width)) => (width, Overflow::Truncate),
+            (None, None) => return None,
+        };
+        let columns = match width {
+            Width::Auto => terminal_width()?,
+            Width::Columns(columns) => columns,
+        };
+        Some(WrapState::new(columns, overflow, self.number_lines, self.show_non_printing))
+    }
+}
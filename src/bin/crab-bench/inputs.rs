@@ -0,0 +1,63 @@
+use std::fs::File;
+use std::io::{BufWriter, Result, Write};
+use std::path::Path;
+
+/// The kinds of input used to benchmark crab, mirroring the test fixtures.
+#[derive(Clone, Copy)]
+pub enum Input {
+    /// Printable ASCII characters, tabs and newlines, in random order.
+    Random,
+    /// Lines of the form `N = <byte N>` for every byte, repeated.
+    AllTheBytes,
+}
+
+impl Input {
+    /// Every kind of input.
+    pub const ALL: [Input; 2] = [Input::Random, Input::AllTheBytes];
+
+    /// The name of the input, used in reports and file names.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Input::Random => "random",
+            Input::AllTheBytes => "all_the_bytes",
+        }
+    }
+
+    /// Writes `size` bytes of this kind of input to `path`.
+    ///
+    /// The contents only depend on the kind and the size, so every run measures the same data.
+    pub fn generate(&self, path: &Path, size: usize) -> Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        match self {
+            Input::Random => {
+                let mut chars: Vec<u8> = (33u8..=0x7E).collect();
+                chars.extend_from_slice(b"\t\n");
+                let mut state: u64 = 0x2545_F491_4F6C_DD1D;
+                for _ in 0..size {
+                    // xorshift64, good enough to spread the characters evenly.
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    writer.write_all(&[chars[(state % chars.len() as u64) as usize]])?;
+                }
+            }
+            Input::AllTheBytes => {
+                let mut block = vec![];
+                for byte in 0..=255u8 {
+                    write!(block, "{} = ", byte)?;
+                    block.extend_from_slice(&[byte, b'\n']);
+                }
+                let mut written = 0;
+                while written < size {
+                    let n = block.len().min(size - written);
+                    writer.write_all(&block[..n])?;
+                    written += n;
+                }
+            }
+        }
+        writer.flush()
+    }
+}
+
+/// The flag combinations benchmarked for each input. The empty set is the plain copy.
+pub const FLAG_SETS: [&[&str]; 6] = [&[], &["-n"], &["-b"], &["-v"], &["-A"], &["-s"]];
@@ -0,0 +1,107 @@
+mod inputs;
+
+use clap::Parser;
+use inputs::{Input, FLAG_SETS};
+use std::env::{current_exe, temp_dir};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{exit, Command, Stdio};
+use std::time::{Duration, Instant};
+
+#[derive(Parser)]
+#[clap(name = "crab-bench")]
+#[clap(about = "Compare crab's throughput with the system cat for each flag set", long_about = None)]
+struct BenchArgs {
+    #[clap(long, value_name = "SIZES", default_value = "1M,16M,64M", use_delimiter = true)]
+    /// input sizes to generate, with an optional K, M or G suffix
+    sizes: Vec<String>,
+    #[clap(long, value_name = "N", default_value = "5")]
+    /// number of runs per measurement, the fastest one is reported
+    runs: usize,
+    #[clap(long, value_name = "PATH")]
+    /// crab binary to benchmark, defaults to the one next to crab-bench
+    crab: Option<PathBuf>,
+    #[clap(long, value_name = "PATH", default_value = "cat")]
+    /// cat binary to compare with
+    cat: PathBuf,
+}
+
+/// Parses a size such as `512K` or `16M` into a number of bytes.
+fn parse_size(size: &str) -> Option<usize> {
+    let (digits, unit) = match size.char_indices().last()? {
+        (i, 'K' | 'k') => (&size[..i], 1 << 10),
+        (i, 'M' | 'm') => (&size[..i], 1 << 20),
+        (i, 'G' | 'g') => (&size[..i], 1 << 30),
+        _ => (size, 1),
+    };
+    digits.parse::<usize>().ok().map(|n| n * unit)
+}
+
+/// Runs `binary` with `flags` on `path` `runs` times, discarding the output, and returns the fastest run.
+fn measure(binary: &Path, flags: &[&str], path: &Path, runs: usize) -> Option<Duration> {
+    (0..runs.max(1))
+        .map(|_| {
+            let start = Instant::now();
+            let status = Command::new(binary)
+                .args(flags)
+                .arg(path)
+                .stdout(Stdio::null())
+                .status()
+                .ok()?;
+            status.success().then(|| start.elapsed())
+        })
+        .collect::<Option<Vec<_>>>()?
+        .into_iter()
+        .min()
+}
+
+/// Returns the throughput in MiB/s of processing `size` bytes in `time`.
+fn throughput(size: usize, time: Duration) -> f64 {
+    size as f64 / (1 << 20) as f64 / time.as_secs_f64()
+}
+
+fn main() {
+    let args = BenchArgs::parse();
+    let crab = args.crab.unwrap_or_else(|| {
+        current_exe()
+            .expect("cannot find the crab-bench binary")
+            .with_file_name(format!("crab{}", std::env::consts::EXE_SUFFIX))
+    });
+    let dir = temp_dir().join(format!("crab-bench-{}", std::process::id()));
+    fs::create_dir_all(&dir).expect("cannot create the input directory");
+
+    println!("{:<14} {:>8} {:<6} {:>11} {:>11} {:>7}", "input", "size", "flags", "crab MiB/s", "cat MiB/s", "ratio");
+    let mut failed = false;
+    for size in &args.sizes {
+        let Some(bytes) = parse_size(size) else {
+            eprintln!("crab-bench: invalid size: {}", size);
+            exit(2);
+        };
+        for input in Input::ALL {
+            let path = dir.join(format!("{}-{}", input.name(), size));
+            input.generate(&path, bytes).expect("cannot write the input");
+            for flags in FLAG_SETS {
+                let crab_time = measure(&crab, flags, &path, args.runs);
+                let cat_time = measure(&args.cat, flags, &path, args.runs);
+                let (Some(crab_time), Some(cat_time)) = (crab_time, cat_time) else {
+                    eprintln!("crab-bench: {} {} failed on {}", input.name(), flags.join(" "), size);
+                    failed = true;
+                    continue;
+                };
+                let (crab_speed, cat_speed) = (throughput(bytes, crab_time), throughput(bytes, cat_time));
+                println!(
+                    "{:<14} {:>8} {:<6} {:>11.1} {:>11.1} {:>7.2}",
+                    input.name(),
+                    size,
+                    flags.join(" "),
+                    crab_speed,
+                    cat_speed,
+                    crab_speed / cat_speed
+                );
+            }
+            let _ = fs::remove_file(&path);
+        }
+    }
+    let _ = fs::remove_dir(&dir);
+    exit(failed as i32)
+}
@@ -0,0 +1,102 @@
+use crate::errors::CrabError;
+use crate::errors::CrabError::OpenError;
+use clap::ArgEnum;
+use sha2::Digest;
+use std::fs::File;
+use std::io::{stderr, Write};
+
+/// The algorithms the inputs and the output can be checksummed with.
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Algorithm {
+    /// SHA-256, as printed by `sha256sum`.
+    Sha256,
+    /// BLAKE3, as printed by `b3sum`.
+    Blake3,
+    /// CRC-32 (IEEE), as eight hexadecimal digits.
+    Crc32,
+}
+
+/// The running state of a checksum.
+pub enum Hasher {
+    /// A SHA-256 checksum.
+    Sha256(sha2::Sha256),
+    /// A BLAKE3 checksum, boxed since its state is large.
+    Blake3(Box<blake3::Hasher>),
+    /// A CRC-32 checksum.
+    Crc32(crc32fast::Hasher),
+}
+
+impl Hasher {
+    /// Creates the state of a checksum with `algorithm` over no data.
+    pub fn new(algorithm: Algorithm) -> Self {
+        match algorithm {
+            Algorithm::Sha256 => Hasher::Sha256(sha2::Sha256::new()),
+            Algorithm::Blake3 => Hasher::Blake3(Box::default()),
+            Algorithm::Crc32 => Hasher::Crc32(crc32fast::Hasher::new()),
+        }
+    }
+
+    /// Adds the bytes of `buf` to the checksum.
+    pub fn update(&mut self, buf: &[u8]) {
+        match self {
+            Hasher::Sha256(hasher) => hasher.update(buf),
+            Hasher::Blake3(hasher) => {
+                hasher.update(buf);
+            }
+            Hasher::Crc32(hasher) => hasher.update(buf),
+        }
+    }
+
+    /// Returns the checksum of all the data added, in lowercase hexadecimal.
+    pub fn finalize(self) -> String {
+        match self {
+            Hasher::Sha256(hasher) => hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect(),
+            Hasher::Blake3(hasher) => hasher.finalize().to_hex().to_string(),
+            Hasher::Crc32(hasher) => format!("{:08x}", hasher.finalize()),
+        }
+    }
+}
+
+/// The checksums computed while copying, one line per input and one for the output, in the
+/// format of `sha256sum`, so the lines of the inputs can be checked with `sha256sum -c`.
+/// Like there, a name with a newline or a backslash is escaped, and its line starts with a
+/// backslash.
+#[derive(Default)]
+pub struct Manifest {
+    /// The checksum and the name of each entry, in the order they were completed.
+    entries: Vec<(String, String)>,
+}
+
+impl Manifest {
+    /// Adds the checksum of the entry called `name`.
+    pub fn push(&mut self, name: &str, checksum: String) {
+        self.entries.push((checksum, name.to_string()));
+    }
+
+    /// Writes the manifest to the file at `path`, or to standard error output if there is none.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `OpenError` if the file cannot be created or written.
+    pub fn write(&self, path: Option<&str>) -> Result<(), CrabError> {
+        let text: String = self.entries.iter().map(|(checksum, name)| line(checksum, name)).collect();
+        match path {
+            Some(path) => File::create(path)
+                .and_then(|mut file| file.write_all(text.as_bytes()))
+                .map_err(|err| OpenError(path.to_string(), err)),
+            None => {
+                let _ = stderr().write_all(text.as_bytes());
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Formats the line of an entry, escaping its name as `sha256sum` does.
+fn line(checksum: &str, name: &str) -> String {
+    if !name.contains(['\\', '\n']) {
+        return format!("{}  {}\n", checksum, name);
+    }
+    let name = name.replace('\\', "\\\\").replace('\n', "\\n");
+    format!("\\{}  {}\n", checksum, name)
+}
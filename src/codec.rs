@@ -0,0 +1,284 @@
+use crate::errors::CrabError::DecodeError;
+use clap::ArgEnum;
+use std::io::{Error, ErrorKind, Write};
+
+/// The encodings the concatenated stream can be converted to or from.
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    /// Base64 with the standard alphabet and `=` padding, as in RFC 4648.
+    Base64,
+    /// Base32 with the standard alphabet and `=` padding, as in RFC 4648.
+    Base32,
+    /// Two lowercase hexadecimal digits per byte.
+    Hex,
+    /// Percent-encoding of every byte but the unreserved characters of RFC 3986.
+    Url,
+}
+
+/// The standard base64 alphabet.
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+/// The standard base32 alphabet.
+const BASE32: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+/// The lowercase hexadecimal digits.
+const HEX: &[u8; 16] = b"0123456789abcdef";
+
+impl Encoding {
+    /// Returns the number of input bytes encoded together, and the number of characters they
+    /// are encoded as.
+    fn group(&self) -> (usize, usize) {
+        match self {
+            Encoding::Base64 => (3, 4),
+            Encoding::Base32 => (5, 8),
+            Encoding::Hex | Encoding::Url => (1, 2),
+        }
+    }
+
+    /// Returns the number of bits each character of the encoding stands for, for the
+    /// encodings that are decoded bit by bit.
+    fn bits(&self) -> u32 {
+        match self {
+            Encoding::Base64 => 6,
+            Encoding::Base32 => 5,
+            Encoding::Hex | Encoding::Url => 4,
+        }
+    }
+
+    /// Returns the name of the encoding, as given on the command line.
+    fn name(&self) -> &'static str {
+        match self {
+            Encoding::Base64 => "base64",
+            Encoding::Base32 => "base32",
+            Encoding::Hex => "hex",
+            Encoding::Url => "url",
+        }
+    }
+
+    /// Returns the value of a character of the encoding, or `None` if it is not one. Base64
+    /// also accepts the URL-safe alphabet, and base32 and hex accept both cases.
+    fn value(&self, character: u8) -> Option<u32> {
+        let value = match (self, character) {
+            (Encoding::Base64, b'A'..=b'Z') => character - b'A',
+            (Encoding::Base64, b'a'..=b'z') => character - b'a' + 26,
+            (Encoding::Base64, b'0'..=b'9') => character - b'0' + 52,
+            (Encoding::Base64, b'+' | b'-') => 62,
+            (Encoding::Base64, b'/' | b'_') => 63,
+            (Encoding::Base32, b'A'..=b'Z') => character - b'A',
+            (Encoding::Base32, b'a'..=b'z') => character - b'a',
+            (Encoding::Base32, b'2'..=b'7') => character - b'2' + 26,
+            (Encoding::Hex | Encoding::Url, b'0'..=b'9') => character - b'0',
+            (Encoding::Hex | Encoding::Url, b'a'..=b'f') => character - b'a' + 10,
+            (Encoding::Hex | Encoding::Url, b'A'..=b'F') => character - b'A' + 10,
+            _ => return None,
+        };
+        Some(value as u32)
+    }
+
+    /// Returns `bytes` encoded on a single line, padded as needed.
+    pub fn encode_all(&self, bytes: &[u8]) -> String {
+        let mut text = Vec::with_capacity(bytes.len() * 3 + 4);
+        for group in bytes.chunks(self.group().0) {
+            self.encode(group, &mut text);
+        }
+        // Every encoding is made of ASCII characters.
+        String::from_utf8(text).unwrap()
+    }
+
+    /// Encodes a group of at most `group().0` bytes, padding it as needed when it is shorter.
+    fn encode(&self, group: &[u8], text: &mut Vec<u8>) {
+        match self {
+            Encoding::Base64 | Encoding::Base32 => {
+                let (bytes, characters) = self.group();
+                let bits = self.bits();
+                let alphabet: &[u8] = if *self == Encoding::Base64 { BASE64 } else { BASE32 };
+                let value = group.iter().enumerate().fold(0u64, |value, (at, byte)| value | (*byte as u64) << (8 * (bytes - 1 - at)));
+                let used = (group.len() * 8).div_ceil(bits as usize);
+                for at in 0..characters {
+                    if at < used {
+                        let shift = bits as usize * (characters - 1 - at);
+                        text.push(alphabet[(value >> shift) as usize & ((1 << bits) - 1)]);
+                    } else {
+                        text.push(b'=');
+                    }
+                }
+            }
+            Encoding::Hex => {
+                for byte in group {
+                    text.extend_from_slice(&[HEX[(byte >> 4) as usize], HEX[(byte & 15) as usize]]);
+                }
+            }
+            Encoding::Url => {
+                for byte in group {
+                    match byte {
+                        b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => text.push(*byte),
+                        _ => write!(text, "%{:02X}", byte).unwrap(),
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The state of an encoding or decoding carried from one write to the next, so the stream
+/// can be converted in chunks of any size.
+pub struct CodecState {
+    /// The encoding converted to or from.
+    encoding: Encoding,
+    /// Whether the stream is decoded rather than encoded.
+    decode: bool,
+    /// The number of characters per line of encoded output, or 0 for no line breaks.
+    width: usize,
+    /// The number of characters on the current line of encoded output.
+    column: usize,
+    /// The bytes of an incomplete group, held back until the rest of it arrives.
+    pending: Vec<u8>,
+    /// The bits decoded but not written yet, the lowest `count` of them.
+    bits: u32,
+    /// The number of bits held in `bits`.
+    count: u32,
+    /// Whether a `%` was seen and the hexadecimal digits of its byte are being read.
+    percent: bool,
+    /// The number of bytes of input seen so far, to locate invalid input.
+    position: u64,
+}
+
+impl CodecState {
+    /// Creates the state at the start of the stream, encoding to `encoding` with lines of
+    /// `width` characters, or decoding from it if `decode` is set. Only base64, base32 and hex
+    /// output is broken into lines.
+    pub fn new(encoding: Encoding, decode: bool, width: usize) -> Self {
+        let width = if encoding == Encoding::Url { 0 } else { width };
+        CodecState { encoding, decode, width, column: 0, pending: vec![], bits: 0, count: 0, percent: false, position: 0 }
+    }
+
+    /// Converts all bytes of the given buffer and writes the result to `writer`, holding back
+    /// an incomplete group until the next call.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `InvalidData` error holding a `DecodeError` if the input is not valid for
+    /// the encoding, or any error writing to `writer`.
+    pub fn format<W: Write>(&mut self, buf: &[u8], writer: &mut W) -> std::io::Result<()> {
+        if self.decode {
+            let mut bytes = Vec::with_capacity(buf.len());
+            for character in buf {
+                if let Err(err) = self.decode(*character, &mut bytes) {
+                    // Write what was decoded before the invalid input, like GNU base64.
+                    writer.write_all(&bytes)?;
+                    return Err(err);
+                }
+                self.position += 1;
+            }
+            return writer.write_all(&bytes);
+        }
+
+        let size = self.encoding.group().0;
+        let mut text = Vec::with_capacity(buf.len() * 2 + 4);
+        let mut rest = buf;
+        // Complete the group held back by the previous call first.
+        if !self.pending.is_empty() {
+            let (head, tail) = rest.split_at(rest.len().min(size - self.pending.len()));
+            self.pending.extend_from_slice(head);
+            rest = tail;
+            if self.pending.len() < size {
+                return Ok(());
+            }
+            self.encoding.encode(&std::mem::take(&mut self.pending), &mut text);
+        }
+        let mut groups = rest.chunks_exact(size);
+        for group in &mut groups {
+            self.encoding.encode(group, &mut text);
+        }
+        self.pending.extend_from_slice(groups.remainder());
+        self.emit(&text, writer)
+    }
+
+    /// Writes whatever is held back once the whole stream has been converted: the padded last
+    /// group and a final line break when encoding.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `InvalidData` error holding a `DecodeError` if the input ends in the middle
+    /// of a byte, or any error writing to `writer`.
+    pub fn finish<W: Write>(&mut self, writer: &mut W) -> std::io::Result<()> {
+        if self.decode {
+            return if self.percent || self.count >= self.encoding.bits() {
+                Err(self.invalid("truncated"))
+            } else {
+                Ok(())
+            };
+        }
+        let mut text = vec![];
+        if !self.pending.is_empty() {
+            self.encoding.encode(&std::mem::take(&mut self.pending), &mut text);
+        }
+        self.emit(&text, writer)?;
+        if self.column > 0 {
+            self.column = 0;
+            writer.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+
+    /// Writes encoded text, breaking it into lines of `width` characters.
+    fn emit<W: Write>(&mut self, text: &[u8], writer: &mut W) -> std::io::Result<()> {
+        if self.width == 0 {
+            return writer.write_all(text);
+        }
+        let mut rest = text;
+        while !rest.is_empty() {
+            let (line, tail) = rest.split_at(rest.len().min(self.width - self.column));
+            writer.write_all(line)?;
+            self.column += line.len();
+            if self.column == self.width {
+                writer.write_all(b"\n")?;
+                self.column = 0;
+            }
+            rest = tail;
+        }
+        Ok(())
+    }
+
+    /// Decodes one character of the input, appending the bytes it completes to `bytes`.
+    /// Whitespace is skipped, except in url input where it stands for itself, and `=` padding
+    /// ends a group, so padded inputs can follow one another.
+    fn decode(&mut self, character: u8, bytes: &mut Vec<u8>) -> std::io::Result<()> {
+        if character.is_ascii_whitespace() && self.encoding != Encoding::Url {
+            return Ok(());
+        }
+        let bits = self.encoding.bits();
+        match (self.encoding, character) {
+            (Encoding::Url, b'%') if !self.percent => {
+                self.percent = true;
+                return Ok(());
+            }
+            (Encoding::Url, _) if !self.percent => {
+                bytes.push(character);
+                return Ok(());
+            }
+            (Encoding::Base64 | Encoding::Base32, b'=') => {
+                // The padding only completes a group whose bytes were all written.
+                if self.count >= bits {
+                    return Err(self.invalid("invalid"));
+                }
+                self.count = 0;
+                return Ok(());
+            }
+            _ => {}
+        }
+        let value = self.encoding.value(character).ok_or_else(|| self.invalid("invalid"))?;
+        self.bits = (self.bits << bits | value) & 0xffff;
+        self.count += bits;
+        if self.count >= 8 {
+            self.count -= 8;
+            bytes.push((self.bits >> self.count) as u8);
+            self.percent = false;
+        }
+        Ok(())
+    }
+
+    /// Returns the error for input that cannot be decoded, at the current position.
+    fn invalid(&self, what: &str) -> Error {
+        let message = format!("{} {} input at byte {}", what, self.encoding.name(), self.position);
+        Error::new(ErrorKind::InvalidData, DecodeError(message))
+    }
+}
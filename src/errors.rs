@@ -1,46 +1,214 @@
-use crate::errors::CrabError::ReadError;
+use crate::args::ARGS;
+use crate::errors::CrabError::{IsDirectory, ReadError, WriteError};
+use crate::json::escape;
 use crate::InputSource;
-use std::env::current_exe;
-use std::io::Error;
-use std::path::PathBuf;
-use CrabError::OpenError;
+use clap::ArgEnum;
+use std::borrow::Cow;
+use std::env::args_os;
+use std::fmt::{Display, Formatter};
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+use CrabError::{DecodeError, HttpError, InputIsOutput, OpenError};
+
+/// How errors are reported on standard error output.
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorFormat {
+    /// A message prefixed with the program name, like coreutils.
+    Text,
+    /// A JSON object per error, with its kind, path, errno and message.
+    Json,
+}
+
+/// What to do when an input cannot be opened or read.
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OnError {
+    /// Report the error and go on with the next input, exiting with status 1 at the end.
+    Continue,
+    /// Report the error and stop, without reading the remaining inputs.
+    Abort,
+    /// Skip the input without reporting it or changing the exit status.
+    Ignore,
+}
 
 /// Errors that Crab can handle
+#[derive(Debug)]
 pub enum CrabError {
     /// Error opening a file. It contains the path and the underlying error.
     OpenError(String, Error),
     /// Error reading from a source. It contains the source and the underlying error.
     ReadError(InputSource, Error),
+    /// An input is the same regular file standard output writes to. It contains the path.
+    InputIsOutput(String),
+    /// An input is a directory. It contains the path and the error reading it returned.
+    IsDirectory(String, Error),
+    /// Error writing to standard output. It contains the underlying error.
+    WriteError(Error),
+    /// A server answered a request for a URL with an error status. It contains the URL and
+    /// the status code.
+    HttpError(String, u16),
+    /// The concatenated input is not valid for the encoding given with `--decode`. It contains
+    /// the description of the problem.
+    DecodeError(String),
 }
 
 impl CrabError {
-    /// Displays the error message to standard error output in the format:
-    /// "[executable name]: [error message]"
-    pub fn show(&mut self) {
-        // Get the name of the executable, or use "crab" if that fails
-        let exe: PathBuf = current_exe().unwrap_or(PathBuf::from("crab"));
-
-        // Print the executable name and a colon on stderr
-        eprint!("{}: ", exe.display());
-
-        // Generate the error message based on the type of error
-        let message: String = match self {
-            // If the error is an OpenError, format the message as "[path]: [error]"
-            OpenError(path, err) => format!("{}: {}", path, err),
-            // If the error is a ReadError, format the message based on the input source
-            ReadError(source, err) => {
-                let name: String = match source {
-                    // If the source is a file, format the name as "file [path]"
-                    InputSource::File(file) => format!("file {}", file),
-
-                    // If the source is stdin, use the name "Stdin"
-                    InputSource::Stdin => String::from("Stdin"),
-                };
-                // Combine the name and the error message with a colon
-                format!("Error reading {}: {}", name, err)
+    /// Creates the error for a failed read, telling directories apart from other failures.
+    pub fn read(source: InputSource, err: Error) -> Self {
+        match source {
+            InputSource::File(path) if err.kind() == ErrorKind::IsADirectory => {
+                IsDirectory(path.to_string_lossy().into_owned(), err)
             }
+            source => ReadError(source, err),
+        }
+    }
+
+    /// Returns whether the error is about an input that does not exist.
+    pub fn is_missing(&self) -> bool {
+        match self {
+            OpenError(_, err) => err.kind() == ErrorKind::NotFound,
+            HttpError(_, status) => matches!(status, 404 | 410),
+            _ => false,
+        }
+    }
+
+    /// Returns the kind of the error: `open`, `read`, `input-is-output`, `is-directory`, `write`,
+    /// `http` or `decode`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            OpenError(..) => "open",
+            ReadError(..) => "read",
+            InputIsOutput(_) => "input-is-output",
+            IsDirectory(..) => "is-directory",
+            WriteError(_) => "write",
+            HttpError(..) => "http",
+            DecodeError(_) => "decode",
+        }
+    }
+
+    /// Returns the path of the input the error is about, `-` for standard input, or `None`
+    /// for errors about the output or the stream as a whole.
+    pub fn path(&self) -> Option<Cow<'_, str>> {
+        match self {
+            OpenError(path, _) | IsDirectory(path, _) | InputIsOutput(path) | HttpError(path, _) => Some(Cow::Borrowed(path)),
+            ReadError(source, _) => Some(source.name()),
+            WriteError(_) | DecodeError(_) => None,
+        }
+    }
+
+    /// Returns the OS error number behind the error, if any.
+    pub fn errno(&self) -> Option<i32> {
+        match self {
+            OpenError(_, err) | ReadError(_, err) | IsDirectory(_, err) | WriteError(err) => err.raw_os_error(),
+            InputIsOutput(_) | HttpError(..) | DecodeError(_) => None,
+        }
+    }
+
+    /// Returns the description of the error, without the path.
+    pub fn message(&self) -> String {
+        match self {
+            OpenError(_, err) | ReadError(_, err) | IsDirectory(_, err) | WriteError(err) => os_message(err),
+            InputIsOutput(_) => String::from("input file is output file"),
+            DecodeError(message) => message.clone(),
+            HttpError(_, status) => match ureq::http::StatusCode::from_u16(*status).ok().and_then(|code| code.canonical_reason()) {
+                Some(reason) => format!("HTTP status {} {}", status, reason),
+                None => format!("HTTP status {}", status),
+            },
+        }
+    }
+
+    /// Returns the error as a JSON object with its kind, path, errno and message.
+    pub fn to_json(&self) -> String {
+        let path = match self.path() {
+            Some(path) => format!("\"{}\"", escape(path.as_bytes())),
+            None => String::from("null"),
         };
-        // Print the error message to standard error output
-        eprintln!("{}", message);
+        let errno = self.errno().map_or(String::from("null"), |errno| errno.to_string());
+        format!(
+            "{{\"kind\":\"{}\",\"path\":{},\"errno\":{},\"message\":\"{}\"}}",
+            self.kind(),
+            path,
+            errno,
+            escape(self.message().as_bytes())
+        )
+    }
+
+    /// Displays the error on standard error output, either as a message in the format
+    /// "[program name]: [error message]" or as a JSON object, depending on `--error-format`.
+    pub fn show(&self) {
+        match ARGS.error_format {
+            ErrorFormat::Text => eprintln!("{}: {}", program_name(), self),
+            ErrorFormat::Json => eprintln!("{}", self.to_json()),
+        }
+    }
+
+    /// Displays a summary of the given errors on standard error output, in the format chosen
+    /// with `--error-format`. Nothing is shown if there were no errors.
+    pub fn show_summary(errors: &[CrabError]) {
+        if errors.is_empty() {
+            return;
+        }
+        match ARGS.error_format {
+            ErrorFormat::Text => {
+                let inputs = if errors.len() == 1 { "input" } else { "inputs" };
+                eprintln!("{}: {} {} failed:", program_name(), errors.len(), inputs);
+                for err in errors {
+                    eprintln!("  {}", err);
+                }
+            }
+            ErrorFormat::Json => {
+                let errors: Vec<String> = errors.iter().map(CrabError::to_json).collect();
+                eprintln!("{{\"kind\":\"summary\",\"failed\":{},\"errors\":[{}]}}", errors.len(), errors.join(","));
+            }
+        }
+    }
+}
+
+impl Display for CrabError {
+    /// Formats the error like coreutils, as "[path]: [message]", or "write error: [message]"
+    /// for errors about the output.
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match (self.path(), self) {
+            (Some(path), _) => write!(f, "{}: {}", path, self.message()),
+            (None, WriteError(_)) => write!(f, "write error: {}", self.message()),
+            (None, _) => write!(f, "{}", self.message()),
+        }
+    }
+}
+
+impl From<Error> for CrabError {
+    /// Turns an error raised while writing the output into the `CrabError` it carries, if it
+    /// carries one, or into a `WriteError` otherwise.
+    fn from(err: Error) -> Self {
+        if !err.get_ref().is_some_and(|inner| inner.is::<CrabError>()) {
+            return WriteError(err);
+        }
+        *err.into_inner().unwrap().downcast::<CrabError>().unwrap()
+    }
+}
+
+impl std::error::Error for CrabError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            OpenError(_, err) | ReadError(_, err) | IsDirectory(_, err) | WriteError(err) => Some(err),
+            InputIsOutput(_) | HttpError(..) | DecodeError(_) => None,
+        }
+    }
+}
+
+/// Returns the name the program was invoked as, without its directory, or "crab".
+fn program_name() -> String {
+    args_os()
+        .next()
+        .as_deref()
+        .and_then(|name| Path::new(name).file_name())
+        .map_or(String::from("crab"), |name| name.to_string_lossy().into_owned())
+}
+
+/// Returns the description of an IO error, without the "(os error N)" suffix std adds to it.
+fn os_message(err: &Error) -> String {
+    let message = err.to_string();
+    match err.raw_os_error() {
+        Some(code) => message.strip_suffix(&format!(" (os error {})", code)).unwrap_or(&message).to_string(),
+        None => message,
     }
 }
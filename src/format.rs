@@ -0,0 +1,522 @@
+use crate::mapping::{new_line, tab, Glyphs};
+use std::io::Write;
+use unicode_width::UnicodeWidthChar;
+
+/// The columns of the tab stops of `--expand-tabs` and `--unexpand`, counted from the start of
+/// the text of a line, after any line number.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TabStops {
+    /// A tab stop every this many columns.
+    Every(usize),
+    /// Tab stops at these columns, in increasing order. There are none past the last one, so
+    /// tabs there stand for a single space, like with GNU expand.
+    List(&'static [usize]),
+}
+
+impl TabStops {
+    /// Returns the first tab stop after `column`, if there is one.
+    fn next(&self, column: usize) -> Option<usize> {
+        match self {
+            TabStops::Every(width) => Some((column / width + 1) * width),
+            TabStops::List(stops) => stops.iter().copied().find(|stop| *stop > column),
+        }
+    }
+}
+
+/// The formatting flags, after merging the options that enable the same things.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub struct FormatOptions {
+    /// Use ^ and M- notation, except for LFD and TAB.
+    pub show_non_printing: bool,
+    /// Show end of lines with $.
+    pub show_ends: bool,
+    /// Display TAB characters as ^I.
+    pub show_tabs: bool,
+    /// Add a number on the line start.
+    pub number_lines: bool,
+    /// Suppress repeated empty output lines.
+    pub squeeze_blank: bool,
+    /// Number nonempty output lines only. Only meaningful with `number_lines`.
+    pub number_non_blank: bool,
+    /// Replace TAB characters with spaces up to the next tab stop, unless they are shown as ^I.
+    pub expand_tabs: Option<TabStops>,
+    /// Replace runs of two or more spaces that end at a tab stop with a TAB, unless TABs are
+    /// shown as ^I or drawn by `show_spaces`.
+    pub unexpand: Option<TabStops>,
+    /// Draw spaces, TABs and no-break spaces with these glyphs. TABs are still shown as ^I
+    /// with `show_tabs`, and no-break spaces in M- notation with `show_non_printing`.
+    pub show_spaces: Option<Glyphs>,
+    /// Color the blank characters at the end of every line.
+    pub highlight_trailing: bool,
+}
+
+impl FormatOptions {
+    /// Returns whether any formatting is enabled. If not, the input is copied as is.
+    pub fn any(&self) -> bool {
+        self.show_non_printing
+            || self.show_ends
+            || self.show_tabs
+            || self.number_lines
+            || self.number_non_blank
+            || self.squeeze_blank
+            || self.expand_tabs.is_some()
+            || self.unexpand.is_some()
+            || self.show_spaces.is_some()
+            || self.highlight_trailing
+    }
+
+    /// Returns the tab stops TABs are expanded to, if they are.
+    fn expand(&self) -> Option<TabStops> {
+        self.expand_tabs.filter(|_| !self.show_tabs)
+    }
+
+    /// Returns the tab stops spaces are turned into TABs at, if they are.
+    fn unexpand(&self) -> Option<TabStops> {
+        self.unexpand.filter(|_| !self.show_tabs && self.show_spaces.is_none())
+    }
+
+    /// Returns whether no-break spaces are told apart from other characters, to draw or
+    /// highlight them. They are already visible in M- notation.
+    fn no_break_spaces(&self) -> bool {
+        (self.show_spaces.is_some() || self.highlight_trailing) && !self.show_non_printing
+    }
+}
+
+/// What to write for a byte after the formatting state has moved over it.
+struct Step {
+    /// Bytes standing for a carriage return or a possible no-break space held back from the
+    /// previous byte, if any.
+    held: &'static [u8],
+    /// The number of spaces held back by `--unexpand` that turned out not to reach a tab stop.
+    spaces: usize,
+    /// The line number to write before the byte, if any.
+    number: Option<usize>,
+    /// How the byte itself is written.
+    print: Print,
+}
+
+/// How a byte is written once the formatting state has moved over it.
+enum Print {
+    /// Not at all, because it is a repeated empty line, a carriage return held back until
+    /// the next byte is known, or a space held back by `--unexpand`.
+    Skip,
+    /// As the flags say, with ^ and M- notation if enabled.
+    Byte,
+    /// As this many spaces, for a TAB expanded by `--expand-tabs`.
+    Spaces(usize),
+    /// As a TAB, for the spaces held back by `--unexpand` up to a tab stop.
+    Tab,
+    /// As a no-break space, whose first byte was held back.
+    NoBreakSpace,
+}
+
+/// The escape sequences around trailing blank characters with `highlight_trailing`, which
+/// give them a red background.
+const HIGHLIGHT: (&[u8], &[u8]) = (b"\x1b[41m", b"\x1b[0m");
+
+/// The state of the formatting carried from one byte to the next. It only depends on the
+/// bytes seen so far, so it can be computed ahead of time for any position of the input.
+#[derive(Clone)]
+pub struct FormatState {
+    /// The flags the output is formatted with.
+    options: FormatOptions,
+    /// A flag that indicates whether the next character starts a new line.
+    line_start: bool,
+    /// A counter for the number of lines written to the output stream.
+    lines_count: usize,
+    /// A flag that indicates whether the last line written to the output stream was empty.
+    last_line_empty: bool,
+    /// A flag that indicates whether the last character was a carriage return that has not
+    /// been written yet, because with `show_ends` it is shown as `^M` before a newline, and
+    /// with `highlight_trailing` the blanks before it are trailing if a newline follows.
+    pending_cr: bool,
+    /// A flag that indicates whether the last byte was the first byte of a possible no-break
+    /// space that has not been written yet.
+    pending_nbsp: bool,
+    /// The display column the text of the current line has reached, after any line number.
+    /// Only kept when tabs are expanded or unexpanded.
+    column: usize,
+    /// The number of continuation bytes still expected for the current UTF-8 character.
+    utf8_needed: u8,
+    /// The bits of the current UTF-8 character seen so far.
+    utf8_char: u32,
+    /// The number of spaces held back by `--unexpand`, which become a TAB if they reach a
+    /// tab stop.
+    blanks: usize,
+    /// The formatted blank characters since the last other character of the line, held back
+    /// by `highlight_trailing` until it is known whether they end the line.
+    trailing: Vec<u8>,
+    /// The number of empty lines removed by `squeeze_blank` so far.
+    squeezed: usize,
+}
+
+impl FormatState {
+    /// Creates the state at the start of the output, formatting with the given flags.
+    pub fn new(options: FormatOptions) -> Self {
+        Self {
+            options,
+            line_start: true,
+            lines_count: 1,
+            last_line_empty: false,
+            pending_cr: false,
+            pending_nbsp: false,
+            column: 0,
+            utf8_needed: 0,
+            utf8_char: 0,
+            blanks: 0,
+            trailing: vec![],
+            squeezed: 0,
+        }
+    }
+
+    /// Move the state over one character. Special characters are handled based on the flags
+    /// in `options`. If the `squeeze_blank` flag is set, empty lines
+    /// are skipped if the last line and the current line are both empty. If the `number_lines`
+    /// flag is set, lines are numbered, and if the `number_non_blank` flag is set, only non-empty
+    /// lines are numbered. A line is empty only if it has no characters at all, like in GNU cat.
+    fn advance(&mut self, character: u8) -> Step {
+        // Resolve a carriage return or the first byte of a no-break space held back by the previous character
+        let visible_cr = self.options.show_non_printing || (self.options.show_ends && character == b'\n');
+        let held: &'static [u8] = match (self.pending_cr, self.pending_nbsp, character) {
+            (true, _, _) if visible_cr => b"^M",
+            (true, _, _) => b"\r",
+            (_, true, 0xa0) | (false, false, _) => b"",
+            (_, true, _) => {
+                self.column += self.width(0xc2);
+                b"\xc2"
+            }
+        };
+        let no_break_space = self.pending_nbsp && character == 0xa0;
+        self.pending_cr = false;
+        self.pending_nbsp = false;
+
+        if character == b'\n' {
+            // Spaces held back at the end of a line are written as they are
+            let spaces = std::mem::take(&mut self.blanks);
+            self.column = 0;
+            self.utf8_needed = 0;
+            // Check if the current line is empty
+            let is_empty = self.line_start;
+            // If the `squeeze_blank` flag is set and the last line was empty and the current line is empty, skip this character
+            if self.options.squeeze_blank && self.last_line_empty && is_empty {
+                self.squeezed += 1;
+                return Step { held, spaces, number: None, print: Print::Skip }
+            }
+            self.last_line_empty = is_empty;
+            self.line_start = true;
+            // Empty lines are numbered here, unless the `number_non_blank` flag is set
+            let number = (self.options.number_lines && !self.options.number_non_blank && is_empty).then(|| self.next_number());
+            return Step { held, spaces, number, print: Print::Byte }
+        }
+
+        // If the `number_lines` flag is set and this character starts a line, take the line number
+        let number = (self.options.number_lines && self.line_start).then(|| self.next_number());
+        self.line_start = false;
+
+        if no_break_space {
+            self.cut_short();
+            self.column += 1;
+            return Step { held, spaces: std::mem::take(&mut self.blanks), number, print: Print::NoBreakSpace };
+        }
+        // The first byte of a no-break space is held back until we know whether the second one follows
+        if character == 0xc2 && self.options.no_break_spaces() {
+            self.pending_nbsp = true;
+            return Step { held, spaces: std::mem::take(&mut self.blanks), number, print: Print::Skip };
+        }
+        let (spaces, print) = self.tabs(character);
+        // With `show_ends`, a carriage return is held back until we know whether a newline follows
+        self.pending_cr = character == b'\r'
+            && ((self.options.show_ends && !self.options.show_non_printing) || self.options.highlight_trailing);
+        let print = if self.pending_cr { Print::Skip } else { print };
+        Step { held, spaces, number, print }
+    }
+
+    /// Count a UTF-8 character cut short by a blank character, which shows as a replacement
+    /// character.
+    fn cut_short(&mut self) {
+        if self.utf8_needed > 0 {
+            self.utf8_needed = 0;
+            self.column += 1;
+        }
+    }
+
+    /// Move the column over a character other than a newline, and decide how it is written
+    /// when tabs are expanded or unexpanded. Returns the number of held back spaces to write
+    /// before the character, and how to write the character itself.
+    fn tabs(&mut self, character: u8) -> (usize, Print) {
+        let (expand, unexpand) = (self.options.expand(), self.options.unexpand());
+        if matches!(character, b'\t' | b' ') {
+            self.cut_short();
+        }
+        match (character, expand, unexpand) {
+            (_, None, None) => (0, Print::Byte),
+            (b'\t', Some(stops), _) => {
+                let stop = stops.next(self.column).unwrap_or(self.column + 1);
+                let spaces = stop - self.column;
+                self.column = stop;
+                (0, Print::Spaces(spaces))
+            }
+            (b'\t', _, Some(stops)) => {
+                // A TAB takes the place of the spaces before it, which are all before its tab stop
+                self.blanks = 0;
+                self.column = stops.next(self.column).unwrap_or(self.column + 1);
+                (0, Print::Byte)
+            }
+            (b' ', _, Some(stops)) => {
+                self.blanks += 1;
+                self.column += 1;
+                match stops.next(self.column - 1) {
+                    // Two or more spaces that reach a tab stop become a TAB
+                    Some(stop) if stop == self.column && self.blanks > 1 => {
+                        self.blanks = 0;
+                        (0, Print::Tab)
+                    }
+                    // A single space is shorter than a TAB, and there are none past the last tab stop
+                    Some(stop) if stop > self.column => (0, Print::Skip),
+                    _ => (std::mem::take(&mut self.blanks) - 1, Print::Byte),
+                }
+            }
+            _ => {
+                self.column += self.width(character);
+                (std::mem::take(&mut self.blanks), Print::Byte)
+            }
+        }
+    }
+
+    /// Returns the number of columns a character other than a TAB or a newline takes on the
+    /// screen once it is written. The width of a multibyte UTF-8 character is counted on its
+    /// last byte.
+    fn width(&mut self, character: u8) -> usize {
+        if self.options.show_non_printing {
+            return match character {
+                32..=126 => 1,
+                0..=31 | 127 => 2,
+                160..=254 => 3,
+                128..=159 | 255 => 4,
+            };
+        }
+        if self.utf8_needed > 0 {
+            if character & 0xc0 == 0x80 {
+                self.utf8_char = self.utf8_char << 6 | (character & 0x3f) as u32;
+                self.utf8_needed -= 1;
+                if self.utf8_needed > 0 {
+                    return 0;
+                }
+                return char::from_u32(self.utf8_char).map_or(1, |character| character.width().unwrap_or(0));
+            }
+            // A character cut short shows as a replacement character
+            self.utf8_needed = 0;
+            return 1 + self.width(character);
+        }
+        let (needed, bits) = match character {
+            32..=126 => return 1,
+            0..=127 => return 0,
+            0xc2..=0xdf => (1, character & 0x1f),
+            0xe0..=0xef => (2, character & 0x0f),
+            0xf0..=0xf4 => (3, character & 0x07),
+            _ => return 1,
+        };
+        self.utf8_needed = needed;
+        self.utf8_char = bits as u32;
+        0
+    }
+
+    /// Returns the number of empty lines removed by `squeeze_blank` so far.
+    pub fn squeezed(&self) -> usize {
+        self.squeezed
+    }
+
+    /// Take the next line number.
+    fn next_number(&mut self) -> usize {
+        self.lines_count += 1;
+        self.lines_count - 1
+    }
+
+    /// Move the state over a buffer without writing anything, as if it had been formatted.
+    /// The blanks held back by `highlight_trailing` are not kept.
+    pub fn skip(&mut self, buf: &[u8]) {
+        // A newline resets everything but the line counting, so only the last line is moved
+        // over byte by byte.
+        let Some(last) = memchr::memrchr(b'\n', buf) else {
+            for character in buf {
+                self.advance(*character);
+            }
+            return;
+        };
+        let mut start = 0;
+        for end in memchr::memchr_iter(b'\n', &buf[..=last]) {
+            self.skip_line(end > start);
+            start = end + 1;
+        }
+        for character in &buf[start..] {
+            self.advance(*character);
+        }
+    }
+
+    /// Move the state over the end of a line, as `advance` does for the bytes of the line and
+    /// its newline. `content` tells whether the line has bytes after the previous position.
+    fn skip_line(&mut self, content: bool) {
+        if content {
+            if self.options.number_lines && self.line_start {
+                self.next_number();
+            }
+            self.line_start = false;
+        }
+        self.pending_cr = false;
+        self.pending_nbsp = false;
+        self.column = 0;
+        self.utf8_needed = 0;
+        self.blanks = 0;
+        let is_empty = self.line_start;
+        if self.options.squeeze_blank && self.last_line_empty && is_empty {
+            self.squeezed += 1;
+            return;
+        }
+        self.last_line_empty = is_empty;
+        self.line_start = true;
+        if self.options.number_lines && !self.options.number_non_blank && is_empty {
+            self.next_number();
+        }
+    }
+
+    /// Format all bytes in the given buffer and write them to `writer`, numbering lines and
+    /// replacing special characters as configured.
+    ///
+    /// # Arguments
+    ///
+    /// * `buf` - A slice of bytes to format.
+    /// * `writer` - The writer that receives the formatted bytes.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `std::io::Error` if there is a problem writing to `writer`.
+    pub fn format<W: Write>(&mut self, buf: &[u8], writer: &mut W) -> std::io::Result<()> {
+        // Without any formatting, the input is copied as is.
+        if !self.options.any() {
+            return writer.write_all(buf);
+        }
+        for character in buf {
+            let Step { held, spaces, number, print } = self.advance(*character);
+            if !self.options.highlight_trailing {
+                writer.write_all(held)?;
+                write_spaces(spaces, writer)?;
+                // Output the line number, if the current line should be numbered
+                if let Some(number) = number {
+                    self.write_number(number, writer)?;
+                }
+                render(&self.options, *character, print, writer)?;
+                continue
+            }
+
+            // Blank characters are held back until the end of the line or the next other character
+            let blank = !matches!(print, Print::Byte) || matches!(character, b' ' | b'\t');
+            write_spaces(spaces, &mut self.trailing)?;
+            if *character == b'\n' || !held.is_empty() || !blank {
+                // They end the line if a newline follows them, possibly after a carriage return
+                self.write_trailing(*character == b'\n' && held != b"\xc2", writer)?;
+            }
+            writer.write_all(held)?;
+            if let Some(number) = number {
+                self.write_number(number, writer)?;
+            }
+            if blank && *character != b'\n' {
+                render(&self.options, *character, print, &mut self.trailing)?;
+            } else {
+                render(&self.options, *character, print, writer)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the blank characters held back by `highlight_trailing`, highlighted if they end
+    /// a line.
+    fn write_trailing<W: Write>(&mut self, highlight: bool, writer: &mut W) -> std::io::Result<()> {
+        if self.trailing.is_empty() {
+            return Ok(());
+        }
+        if highlight {
+            writer.write_all(HIGHLIGHT.0)?;
+            writer.write_all(&self.trailing)?;
+            writer.write_all(HIGHLIGHT.1)?;
+        } else {
+            writer.write_all(&self.trailing)?;
+        }
+        self.trailing.clear();
+        Ok(())
+    }
+
+    /// Write whatever is still held back once the whole input has been formatted.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `std::io::Error` if there is a problem writing to `writer`.
+    pub fn finish<W: Write>(&mut self, writer: &mut W) -> std::io::Result<()> {
+        // The last line ends with the input, even without a newline
+        write_spaces(std::mem::take(&mut self.blanks), &mut self.trailing)?;
+        self.write_trailing(true, writer)?;
+        if std::mem::take(&mut self.pending_nbsp) {
+            writer.write_all(b"\xc2")?;
+        }
+        if std::mem::take(&mut self.pending_cr) {
+            writer.write_all(if self.options.show_non_printing { b"^M" } else { b"\r" })?;
+        }
+        Ok(())
+    }
+
+    /// Write the number of a line, followed by a TAB, or by the spaces up to the next multiple
+    /// of 8 columns when tabs are expanded.
+    fn write_number<W: Write>(&self, number: usize, writer: &mut W) -> std::io::Result<()> {
+        if self.options.expand().is_none() {
+            return write!(writer, "{:>6}\t", number);
+        }
+        let gutter = format!("{:>6}", number);
+        write!(writer, "{}{:2$}", gutter, "", 8 - gutter.len() % 8)
+    }
+}
+
+/// Write a character as the flags say, once the formatting state has moved over it.
+fn render<W: Write>(options: &FormatOptions, character: u8, print: Print, writer: &mut W) -> std::io::Result<()> {
+    let glyphs = options.show_spaces;
+    match print {
+        Print::Skip => return Ok(()),
+        Print::Byte => {}
+        Print::Spaces(spaces) => {
+            return match glyphs {
+                Some(glyphs) => {
+                    writer.write_all(glyphs.tab())?;
+                    write_spaces(spaces - 1, writer)
+                }
+                None => write_spaces(spaces, writer),
+            }
+        }
+        Print::Tab => return writer.write_all(b"\t"),
+        Print::NoBreakSpace => return writer.write_all(glyphs.map_or("\u{a0}".as_bytes(), Glyphs::no_break_space)),
+    }
+
+    // Output the current character according to the settings and any special character handling
+    match (character, glyphs) {
+        (b' ', Some(glyphs)) => writer.write_all(glyphs.space()),
+        (b'\t', Some(glyphs)) if !options.show_tabs => writer.write_all(glyphs.tab()),
+        (b'\t', _) => writer.write_all(tab(options.show_tabs)),
+        (b'\n', _) => writer.write_all(new_line(options.show_ends)),
+        _ if !options.show_non_printing => writer.write_all(&[character]),
+        (0..=8 | 11..=31, _) => writer.write_all(&[b'^', character + 64]),
+        (127, _) => writer.write_all(b"^?"),
+        (128..=159, _) => writer.write_all(&[b'M', b'-', b'^', character - 64]),
+        (160..=254, _) => writer.write_all(&[b'M', b'-', character - 128]),
+        (255, _) => writer.write_all(b"M-^?"),
+        _ => writer.write_all(&[character])
+    }
+}
+
+/// Write `count` spaces.
+fn write_spaces<W: Write>(count: usize, writer: &mut W) -> std::io::Result<()> {
+    const SPACES: &[u8; 64] = &[b' '; 64];
+    let mut left = count;
+    while left > 0 {
+        let now = left.min(SPACES.len());
+        writer.write_all(&SPACES[..now])?;
+        left -= now;
+    }
+    Ok(())
+}
@@ -0,0 +1,149 @@
+use crate::codec::Encoding;
+use crate::reader::InputSource;
+use clap::ArgEnum;
+use std::io::Write;
+
+/// How the output is rendered.
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// The input itself, formatted by the usual flags.
+    Text,
+    /// A JSON array with an object for every line.
+    Json,
+    /// A JSON object for every line, one per output line.
+    Jsonl,
+}
+
+/// The state of the JSON rendering carried from one write to the next.
+///
+/// Every input line becomes an object such as
+/// `{"source":"a.txt","line":3,"offset":42,"text":"crab","ending":"lf"}`, where `line` and
+/// `offset` are the line number and the byte offset of the line start within its source, and
+/// `ending` is one of `lf`, `crlf` or `none` for a last line without a newline. The output is
+/// strict JSON: bytes of the text that are not valid UTF-8 are replaced with U+FFFD, and the
+/// exact bytes of such a line are given in base64 by an extra `text_base64` field.
+pub struct JsonState {
+    /// Whether the objects are written as a single JSON array rather than one per line.
+    array: bool,
+    /// The name of the source being rendered, already escaped.
+    source: String,
+    /// The number of the current line within its source.
+    line: u64,
+    /// The byte offset of the current line within its source.
+    offset: u64,
+    /// The bytes of the current line seen so far.
+    pending: Vec<u8>,
+    /// Whether any object has been written yet.
+    started: bool,
+}
+
+impl JsonState {
+    /// Creates the state at the start of the output, writing a JSON array if `array` is set
+    /// and one object per line otherwise.
+    pub fn new(array: bool) -> Self {
+        JsonState { array, source: escape(b"-"), line: 1, offset: 0, pending: vec![], started: false }
+    }
+
+    /// Starts rendering the lines of a new source, numbering them from 1.
+    pub fn start_source(&mut self, source: &InputSource) {
+        self.source = escape(source.name().as_bytes());
+        self.line = 1;
+        self.offset = 0;
+    }
+
+    /// Writes the last line of the current source if it has no newline.
+    pub fn end_source<W: Write>(&mut self, writer: &mut W) -> std::io::Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let text = std::mem::take(&mut self.pending);
+        self.record(&text, "none", writer)?;
+        self.offset += text.len() as u64;
+        self.line += 1;
+        Ok(())
+    }
+
+    /// Renders all complete lines of the given buffer, holding back the start of a line that
+    /// does not end in it.
+    pub fn format<W: Write>(&mut self, buf: &[u8], writer: &mut W) -> std::io::Result<()> {
+        let mut rest = buf;
+        while let Some(end) = rest.iter().position(|&byte| byte == b'\n') {
+            let len = self.pending.len() + end + 1;
+            if self.pending.is_empty() {
+                self.line_record(&rest[..end], writer)?;
+            } else {
+                self.pending.extend_from_slice(&rest[..end]);
+                let line = std::mem::take(&mut self.pending);
+                self.line_record(&line, writer)?;
+            }
+            self.offset += len as u64;
+            self.line += 1;
+            rest = &rest[end + 1..];
+        }
+        self.pending.extend_from_slice(rest);
+        Ok(())
+    }
+
+    /// Writes the pending line and closes the array, once the whole input has been rendered.
+    pub fn finish<W: Write>(&mut self, writer: &mut W) -> std::io::Result<()> {
+        self.end_source(writer)?;
+        match (self.array, self.started) {
+            (true, true) => writer.write_all(b"\n]\n"),
+            (true, false) => writer.write_all(b"[]\n"),
+            (false, _) => Ok(()),
+        }
+    }
+
+    /// Writes the object of a line that ended with a newline, which is not part of `line`.
+    fn line_record<W: Write>(&mut self, line: &[u8], writer: &mut W) -> std::io::Result<()> {
+        match line.strip_suffix(b"\r") {
+            Some(text) => self.record(text, "crlf", writer),
+            None => self.record(line, "lf", writer),
+        }
+    }
+
+    /// Writes the object of the current line.
+    fn record<W: Write>(&mut self, text: &[u8], ending: &str, writer: &mut W) -> std::io::Result<()> {
+        let separator: &[u8] = match (self.array, self.started) {
+            (true, false) => b"[\n",
+            (true, true) => b",\n",
+            (false, _) => b"",
+        };
+        self.started = true;
+        writer.write_all(separator)?;
+        write!(
+            writer,
+            "{{\"source\":\"{}\",\"line\":{},\"offset\":{},\"text\":\"{}\",",
+            self.source,
+            self.line,
+            self.offset,
+            escape(text)
+        )?;
+        if std::str::from_utf8(text).is_err() {
+            write!(writer, "\"text_base64\":\"{}\",", Encoding::Base64.encode_all(text))?;
+        }
+        write!(writer, "\"ending\":\"{}\"}}", ending)?;
+        if !self.array {
+            writer.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+}
+
+/// Escapes bytes for the inside of a JSON string. Bytes that are not part of valid UTF-8 are
+/// replaced with U+FFFD, so the string is valid for any JSON parser.
+pub fn escape(bytes: &[u8]) -> String {
+    let mut escaped = String::with_capacity(bytes.len());
+    for character in String::from_utf8_lossy(bytes).chars() {
+        match character {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            '\u{0}'..='\u{1f}' | '\u{7f}' => escaped.push_str(&format!("\\u{:04x}", character as u32)),
+            _ => escaped.push(character),
+        }
+    }
+    escaped
+}
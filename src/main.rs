@@ -1,54 +1,223 @@
 mod args;
+mod checksum;
+mod codec;
 mod errors;
+mod format;
+mod json;
 mod reader;
 mod mapping;
+#[cfg(unix)]
+mod mmap;
+mod output;
+mod pipeline;
+mod stats;
 mod writer;
+mod wrap;
 #[cfg(test)]
 mod test;
 
-use std::io::{copy, stdout};
 use crate::args::ARGS;
-use crate::reader::{InputSource, Reader};
+use crate::checksum::{Hasher, Manifest};
+use crate::errors::{CrabError, OnError};
+use crate::format::FormatOptions;
+use crate::json::OutputFormat;
+use crate::output::Output;
+use crate::reader::{InputSource, Reader, SourceList, SourceWriter};
+use std::io::{ErrorKind, Write};
 use std::process::exit;
-use crate::writer::OutWriter;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use crate::pipeline::Pipeline;
+use crate::writer::{Checksums, HashWriter, OutWriter, Stats, Tee, Transcoder, Wrapper};
 
 /// The main function of the program.
 fn main() {
+    // Check if any of the output options are enabled.
+    let formatted = ARGS.format().any()
+        || ARGS.output_format != OutputFormat::Text
+        || ARGS.codec().is_some()
+        || ARGS.checksum.is_some()
+        || ARGS.stats
+        || ARGS.fit().is_some();
+
+    // Open the output, followed by the files given with --tee. When one of them is also an
+    // input, it is written through a temporary file so it is not truncated before being read.
+    // The names read from --files-from are not known yet, so any of them may be.
+    let mut outputs = vec![];
+    let inputs = ARGS.files_from.is_none().then_some(ARGS.files.as_slice());
+    let paths = std::iter::once((ARGS.output.as_deref(), ARGS.append, ARGS.atomic))
+        .chain(ARGS.tee.iter().map(|tee| (Some(tee.path.as_str()), false, false)));
+    for (path, append, atomic) in paths {
+        match Output::open(path, append, atomic, inputs) {
+            Ok(output) => outputs.push(output),
+            Err(err) => {
+                err.show();
+                // Remove the temporary files of the outputs opened so far.
+                drop(outputs);
+                exit(1)
+            }
+        }
+    }
+
     // Create a new reader with the input sources specified in the command-line arguments.
-    let mut reader = Reader::new(get_sources(&ARGS.files));
+    // Large files are mapped into memory when the output is rendered, so the writers can
+    // process them in place.
+    let mut sources = get_sources(&ARGS.files, ARGS.files_from.as_deref(), ARGS.null);
+    if ARGS.list {
+        // List the members of every archive instead of reading it.
+        sources = Box::new(sources.map(|source| source.and_then(InputSource::into_listing)));
+    }
+    let identities = outputs.iter().filter_map(Output::identity).collect();
+    let rendered = formatted || outputs.len() > 1;
+    let on_error = ARGS.on_error.unwrap_or(OnError::Continue);
+    // A timeout of zero, or too long to represent, waits as long as it takes.
+    let fifo_timeout = Duration::try_from_secs_f64(ARGS.fifo_timeout).ok().filter(|timeout| !timeout.is_zero());
+    let http_timeout = Duration::try_from_secs_f64(ARGS.http_timeout).ok().filter(|timeout| !timeout.is_zero());
+    let mut reader = Reader::new(sources, identities, rendered, fifo_timeout, http_timeout, on_error, ARGS.missing_ok);
+    if ARGS.files_from.is_some() {
+        reader.read_in_turn();
+    }
 
-    // Check if any of the output options are enabled.
-    if !ARGS.show_non_printing && !ARGS.show_ends && !ARGS.show_tabs && !ARGS.number_lines && !ARGS.number_non_blank && !ARGS.squeeze_blank {
-        // If none of the output options are enabled, just copy the input to standard output.
-        copy(&mut reader, &mut stdout().lock()).unwrap();
-    } else {
-        // Otherwise, create a new OutWriter and copy the modified input to it.
-        let mut writer = OutWriter::new();
-        copy(&mut reader, &mut writer).unwrap();
+    // The outputs are only completed if everything could be written to them, otherwise any
+    // temporary file is removed when it is dropped.
+    let result = copy(&mut reader, &outputs, formatted)
+        .and_then(|manifest| outputs.into_iter().try_for_each(Output::commit).map(|_| manifest));
+    let manifest = match result {
+        Ok(manifest) => manifest,
+        Err(err) => {
+            // A closed pipe ends the output quietly, as it does for cat when SIGPIPE kills it.
+            if err.kind() != ErrorKind::BrokenPipe {
+                CrabError::from(err).show();
+            }
+            exit(1)
+        }
+    };
+
+    // Write the checksums of the inputs and the output, once the output is complete.
+    if let Err(err) = manifest.map_or(Ok(()), |manifest| manifest.write(ARGS.checksum_file.as_deref())) {
+        err.show();
+        exit(1)
+    }
+
+    // Sum up the failures when an error policy was chosen, so they are not lost in the output.
+    if ARGS.on_error.is_some() {
+        CrabError::show_summary(&reader.failures);
     }
 
     // Exit the program with the exit code from the reader.
-    exit(reader.exit_code as i32)
+    exit(reader.exit_code)
 }
 
+/// Copies the input sources of `reader` to the outputs: the main output first, then one for
+/// each --tee file. Returns the checksums of the inputs and of the main output, if --checksum
+/// asked for them.
+fn copy(reader: &mut Reader, outputs: &[Output], formatted: bool) -> std::io::Result<Option<Manifest>> {
+    if !formatted && outputs.len() == 1 {
+        // If none of the output options are enabled, just copy the input to the output.
+        if outputs[0].is_stdout() {
+            reader.copy_to_stdout()?;
+        } else {
+            reader.copy_to(&mut outputs[0].writer()?)?;
+        }
+        return Ok(None);
+    }
 
-/// Returns a vector of input sources based on a list of file names.
+    // The main output is checksummed as it is written, after it has been rendered.
+    let mut main = outputs[0].writer()?;
+    let output_hash = ARGS.checksum.map(|algorithm| Arc::new(Mutex::new(Hasher::new(algorithm))));
+    if let Some(hasher) = &output_hash {
+        main = Box::new(HashWriter::new(main, hasher.clone()));
+    }
+    // The lines of the main output are fitted in the width once rendered, unless they are JSON.
+    if let Some(state) = ARGS.fit().filter(|_| ARGS.output_format == OutputFormat::Text) {
+        main = Box::new(Wrapper::new(main, state));
+    }
+
+    // Every output renders the input on its own, the main one as the command line says and
+    // each --tee file with its own flags, if it has any.
+    let mut writers = vec![renderer(main, None)];
+    for (tee, output) in ARGS.tee.iter().zip(&outputs[1..]) {
+        writers.push(renderer(output.writer()?, tee.options));
+    }
+    let mut writer: Box<dyn SourceWriter> = Box::new(Tee::new(writers));
+    // The inputs are counted as the outputs receive them, after any conversion.
+    if ARGS.stats {
+        writer = Box::new(Stats::new(writer, ARGS.squeeze_blank));
+    }
+    // The input is encoded or decoded as a whole, before any output renders it.
+    if let Some(codec) = ARGS.codec() {
+        writer = Box::new(Transcoder::new(writer, codec));
+    }
+    let (Some(algorithm), Some(output_hash)) = (ARGS.checksum, output_hash) else {
+        reader.copy_to(&mut writer)?;
+        writer.finish()?;
+        return Ok(None);
+    };
+
+    // The inputs are checksummed as they are read, before any conversion.
+    let mut checksums = Checksums::new(writer, algorithm);
+    reader.copy_to(&mut checksums)?;
+    checksums.finish()?;
+    let mut manifest = std::mem::take(&mut checksums.manifest);
+    let output = std::mem::replace(&mut *output_hash.lock().unwrap(), Hasher::new(algorithm));
+    // Standard output is labeled apart from a `-` input, since it cannot be checked again.
+    manifest.push(ARGS.output.as_deref().unwrap_or("(output)"), output.finalize());
+    Ok(Some(manifest))
+}
+
+/// Creates the writer rendering the input to `writer`, formatted with `options`, or as the
+/// command-line arguments say if there are none.
+fn renderer(writer: Box<dyn Write + Send>, options: Option<FormatOptions>) -> Box<dyn SourceWriter> {
+    if let Some(options) = options {
+        return Box::new(OutWriter::new(writer, options));
+    }
+    let options = ARGS.format();
+    if ARGS.output_format != OutputFormat::Text {
+        // Structured output describes the lines of each source, and ignores the formatting flags.
+        Box::new(OutWriter::json(writer, ARGS.output_format == OutputFormat::Json))
+    } else if ARGS.threads > 1 && options.any() && !options.highlight_trailing {
+        // If more than one thread was requested, format the input on a pipeline of worker threads.
+        // Highlighted blanks are held back across chunks, so they are formatted on one thread.
+        Box::new(Pipeline::new(ARGS.threads, options, writer))
+    } else {
+        // Otherwise, create a new OutWriter and copy the modified input to it.
+        Box::new(OutWriter::new(writer, options))
+    }
+}
+
+/// Returns an iterator of input sources based on a list of file names and an optional file list.
 ///
-/// If the list is empty, the vector will contain only `Stdin`.
-/// If a file name is "-", it will be replaced with `Stdin`.
+/// If both are empty, the iterator will yield only `Stdin`.
+/// If a file name is "-", it will be replaced with `Stdin`, and a name such as
+/// `bundle.tar.gz!etc/config.yaml` names a `Member` of an archive and `unix:/run/app.sock`
+/// a `Socket`, while `http://` and `https://` names are a `Url`, and `fd:N`, `/dev/fd/N` and
+/// `/dev/stdin` an inherited `Descriptor`.
 /// Otherwise, the file name will be used to create a `File` input source.
-fn get_sources(files: &[String]) -> Vec<InputSource> {
-    if files.is_empty() {
-        // If the list of file names is empty, return a vector containing only `Stdin`.
-        return vec![InputSource::Stdin];
+/// The names read from `files_from` follow the ones given as arguments, and are only read
+/// when the previous sources have been consumed.
+fn get_sources(
+    files: &[String],
+    files_from: Option<&str>,
+    null: bool,
+) -> Box<dyn Iterator<Item = Result<InputSource, CrabError>>> {
+    if files.is_empty() && files_from.is_none() {
+        // If the list of file names is empty, return an iterator yielding only `Stdin`.
+        return Box::new(std::iter::once(Ok(InputSource::Stdin)));
     }
 
     // Use the `map` method to transform each file name into an input source.
-    let res: Vec<InputSource> = files
+    let res = files
         .iter()
-        .map(|file| if file == "-" { InputSource::Stdin } else { InputSource::File(file.clone()) })
-        .collect();
+        .map(|file| Ok(InputSource::parse(file.clone())))
+        .collect::<Vec<_>>()
+        .into_iter();
 
-    // Return the result vector.
-    res
+    // Append the names from the file list, if any.
+    match files_from {
+        Some(list) => {
+            let delimiter = if null { b'\0' } else { b'\n' };
+            Box::new(res.chain(SourceList::new(list.to_string(), delimiter)))
+        }
+        None => Box::new(res),
+    }
 }
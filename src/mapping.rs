@@ -1,49 +1,76 @@
-use lazy_static::lazy_static;
-use crate::args::ARGS;
-
 /**
 This function returns a reference to a static byte slice representing the tab character.
 
 The tab character can be displayed as a visible tab (^I) or a regular tab (\t), depending
-on the value of the show_tabs field of the ARGS global variable.
+on the value of the show_tabs argument.
 
 The TAB constant holds the byte slice representing a regular tab.
 
 The VISIBLE_TAB constant holds the byte slice representing a visible tab (^I).
 
-The FINAL_TAB lazy static variable holds a reference to the byte slice that will be returned by the function.
-
 If show_tabs is true, the visible tab byte slice is returned. Otherwise, the regular tab byte slice is returned.
  */
-pub fn tab() -> &'static [u8] {
+pub fn tab(show_tabs: bool) -> &'static [u8] {
     const TAB: &[u8; 1] = b"\t";
     const VISIBLE_TAB: &[u8; 2] = b"^I";
-    lazy_static! {
-        static ref FINAL_TAB: &'static [u8] = if ARGS.show_tabs {
-            VISIBLE_TAB
-        } else {
-            TAB
-        };
+    if show_tabs {
+        VISIBLE_TAB
+    } else {
+        TAB
     }
-    &FINAL_TAB
 }
 
 /**
 This function returns a static reference to a byte slice representing a newline character.
 The returned byte slice is either a visible newline character or a regular newline character,
-depending on the value of the ARGS.show_ends boolean value.
+depending on the value of the show_ends argument.
 ## Returns
 A reference to a static byte slice representing a newline character.
  */
-pub fn new_line() -> &'static [u8] {
+pub fn new_line(show_ends: bool) -> &'static [u8] {
     const NEW_LINE: &[u8; 1] = b"\n";
     const VISIBLE_NEW_LINE: &[u8; 2] = b"$\n";
-    lazy_static! {
-        static ref FINAL_NEW_LINE: &'static [u8] = if ARGS.show_ends {
-            VISIBLE_NEW_LINE
-        } else {
-            NEW_LINE
-        };
+    if show_ends {
+        VISIBLE_NEW_LINE
+    } else {
+        NEW_LINE
+    }
+}
+
+/**
+The glyphs `--show-spaces` draws blank characters with: Unicode symbols, or ASCII
+characters for terminals and fonts that lack them.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Glyphs {
+    /// `·`, `→` and `␣`.
+    Unicode,
+    /// `.`, `>` and `+`.
+    Ascii,
+}
+
+impl Glyphs {
+    /// Returns the glyph standing for a space, `·` or `.`.
+    pub fn space(self) -> &'static [u8] {
+        match self {
+            Glyphs::Unicode => "·".as_bytes(),
+            Glyphs::Ascii => b".",
+        }
+    }
+
+    /// Returns the glyph standing for a TAB, `→` or `>`.
+    pub fn tab(self) -> &'static [u8] {
+        match self {
+            Glyphs::Unicode => "→".as_bytes(),
+            Glyphs::Ascii => b">",
+        }
+    }
+
+    /// Returns the glyph standing for a no-break space (U+00A0), `␣` or `+`.
+    pub fn no_break_space(self) -> &'static [u8] {
+        match self {
+            Glyphs::Unicode => "␣".as_bytes(),
+            Glyphs::Ascii => b"+",
+        }
     }
-    &FINAL_NEW_LINE
 }
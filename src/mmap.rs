@@ -0,0 +1,169 @@
+use memmap2::{Advice, Mmap};
+use std::fs::File;
+use std::io::{Result, Write};
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Once;
+
+/// Files smaller than this are read normally, since mapping them costs more than copying.
+pub const MMAP_THRESHOLD: u64 = 512 * 1024;
+
+/// The size of the slices handed to the writer. Each one is checked for truncation first.
+pub const WINDOW_SIZE: usize = 8 * 1024 * 1024;
+
+/// The start address of the mapping being read, used by the SIGBUS handler.
+static MAP_START: AtomicUsize = AtomicUsize::new(0);
+/// The end address of the mapping being read, used by the SIGBUS handler.
+static MAP_END: AtomicUsize = AtomicUsize::new(0);
+/// Set by the SIGBUS handler when a page of the mapping is no longer backed by the file.
+static TRUNCATED: AtomicBool = AtomicBool::new(false);
+/// The size of a memory page.
+static PAGE_SIZE: AtomicUsize = AtomicUsize::new(4096);
+
+/// A regular file mapped into memory, so its contents can be handed to a writer as slices
+/// of the mapping instead of being copied through a read buffer.
+pub struct MappedFile {
+    /// The opened file, kept to check its size if it is truncated while mapped.
+    file: File,
+    /// The read-only mapping of the whole file.
+    map: Mmap,
+}
+
+impl MappedFile {
+    /// Maps a file into memory.
+    ///
+    /// Only regular files of at least `MMAP_THRESHOLD` bytes are mapped. Anything else, or
+    /// files the kernel refuses to map such as those in procfs, is handed back so it can be
+    /// read normally.
+    ///
+    /// # Arguments
+    ///
+    /// * `file` - The file to map.
+    ///
+    /// # Returns
+    ///
+    /// The mapped file, or the original file if it cannot or should not be mapped.
+    pub fn map(file: File) -> std::result::Result<Self, File> {
+        match file.metadata() {
+            Ok(meta) if meta.is_file() && meta.len() >= MMAP_THRESHOLD => {}
+            _ => return Err(file),
+        }
+        // SAFETY: the mapping is read-only, and pages that disappear because the file is
+        // truncated while mapped are replaced by the SIGBUS handler instead of crashing.
+        match unsafe { Mmap::map(&file) } {
+            Ok(map) => {
+                let _ = map.advise(Advice::Sequential);
+                install_sigbus_handler();
+                Ok(MappedFile { file, map })
+            }
+            Err(_) => Err(file),
+        }
+    }
+
+    /// Writes the contents of the mapping to the given writer, one window at a time.
+    ///
+    /// Every page of a window is touched, and the size of the file checked, before the window
+    /// is written. If the file was truncated, the missing pages fault and the SIGBUS handler
+    /// maps zeros over them, or the end of its last page reads as zeros, so only the part of
+    /// the window before the new end of the file is written, and the output stops there, as
+    /// it would when reading.
+    ///
+    /// # Arguments
+    ///
+    /// * `writer` - The writer that receives the data.
+    ///
+    /// # Returns
+    ///
+    /// `false` if the file was truncated while a window was being written, in which case
+    /// the end of that window may have been replaced by zeros.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing to the writer fails.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<bool> {
+        let page = PAGE_SIZE.load(Ordering::Relaxed);
+        let start = self.map.as_ptr() as usize;
+        TRUNCATED.store(false, Ordering::SeqCst);
+        MAP_START.store(start, Ordering::SeqCst);
+        MAP_END.store(start + self.map.len(), Ordering::SeqCst);
+
+        let result = (|| {
+            for offset in (0..self.map.len()).step_by(WINDOW_SIZE) {
+                let end = self.map.len().min(offset + WINDOW_SIZE);
+                // Touch every page of the window so a truncation shows up before writing.
+                for index in (offset..end).step_by(page) {
+                    // SAFETY: the index is within the mapping.
+                    unsafe { ptr::read_volatile(self.map.as_ptr().add(index)) };
+                }
+                // Stop at the end of the file if it is now shorter, even if no page faulted.
+                if TRUNCATED.load(Ordering::SeqCst) || self.len() < end {
+                    writer.write_all(&self.map[offset..self.len().clamp(offset, end)])?;
+                    return Ok(true);
+                }
+                writer.write_all(&self.map[offset..end])?;
+                if TRUNCATED.load(Ordering::SeqCst) || self.len() < end {
+                    return Ok(false);
+                }
+            }
+            Ok(true)
+        })();
+
+        MAP_START.store(0, Ordering::SeqCst);
+        MAP_END.store(0, Ordering::SeqCst);
+        result
+    }
+
+    /// Returns the current size of the file, or the size of the mapping if it cannot be known.
+    fn len(&self) -> usize {
+        self.file.metadata().map_or(self.map.len(), |meta| meta.len() as usize)
+    }
+}
+
+/// Installs the SIGBUS handler the first time a file is mapped.
+fn install_sigbus_handler() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| unsafe {
+        // SAFETY: sysconf and sigaction are called with valid arguments.
+        let page = libc::sysconf(libc::_SC_PAGESIZE);
+        if page > 0 {
+            PAGE_SIZE.store(page as usize, Ordering::Relaxed);
+        }
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = on_sigbus as *const () as usize;
+        action.sa_flags = libc::SA_SIGINFO;
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(libc::SIGBUS, &action, ptr::null_mut());
+    });
+}
+
+/// Handles a SIGBUS raised by reading a page past the end of a truncated mapped file.
+///
+/// The faulting page is replaced by an anonymous zero page so the read can complete, and
+/// the truncation is recorded. Faults outside the mapping restore the default action, so
+/// returning from the handler re-raises them and the process terminates as usual.
+extern "C" fn on_sigbus(_signal: libc::c_int, info: *mut libc::siginfo_t, _context: *mut libc::c_void) {
+    // SAFETY: the kernel passes a valid siginfo_t to handlers installed with SA_SIGINFO.
+    let address = unsafe { (*info).si_addr() } as usize;
+    let start = MAP_START.load(Ordering::SeqCst);
+    let end = MAP_END.load(Ordering::SeqCst);
+    if address >= start && address < end {
+        let page = address & !(PAGE_SIZE.load(Ordering::Relaxed) - 1);
+        // SAFETY: the page belongs to the mapping being read, which stays alive while it is registered.
+        let replaced = unsafe {
+            libc::mmap(
+                page as *mut libc::c_void,
+                PAGE_SIZE.load(Ordering::Relaxed),
+                libc::PROT_READ,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_FIXED,
+                -1,
+                0,
+            )
+        };
+        if replaced != libc::MAP_FAILED {
+            TRUNCATED.store(true, Ordering::SeqCst);
+            return;
+        }
+    }
+    // SAFETY: restoring the default action is async-signal-safe.
+    unsafe { libc::signal(libc::SIGBUS, libc::SIG_DFL) };
+}
@@ -0,0 +1,184 @@
+use crate::errors::CrabError;
+use crate::errors::CrabError::OpenError;
+use std::fs::{File, OpenOptions};
+use std::io::{stdout, ErrorKind, Result, Write};
+#[cfg(unix)]
+use std::os::fd::AsFd;
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// Where the output goes.
+pub enum Output {
+    /// Standard output.
+    Stdout,
+    /// A file, truncated or appended to.
+    File(File),
+    /// A temporary file next to `path`, renamed over it once the output is complete, so the
+    /// file at `path` is never seen half-written.
+    Atomic {
+        /// The temporary file.
+        file: File,
+        /// The path of the temporary file.
+        temp: PathBuf,
+        /// The path the temporary file replaces.
+        path: PathBuf,
+    },
+}
+
+impl Output {
+    /// Opens the destination of the output.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The file to write to, or `None` for standard output.
+    /// * `append` - Append to the file instead of truncating it.
+    /// * `atomic` - Write to a temporary file, and rename it over `path` on `commit`.
+    /// * `inputs` - The file names given as inputs, or `None` if they are only known once read,
+    ///   as with --files-from. If the file is one of them, or standard input, truncating it
+    ///   would lose data before it is read, so it is written through a temporary file as with
+    ///   `atomic`. When the inputs are not known, any existing regular file is.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `OpenError` if the file or the temporary file cannot be created.
+    pub fn open(path: Option<&str>, append: bool, atomic: bool, inputs: Option<&[String]>) -> std::result::Result<Self, CrabError> {
+        let Some(name) = path else {
+            return Ok(Output::Stdout);
+        };
+        let path = PathBuf::from(name);
+        if atomic || (!append && is_input(&path, inputs)) {
+            return Self::atomic(path).map_err(|err| OpenError(name.to_string(), err));
+        }
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .append(append)
+            .truncate(!append)
+            .open(&path)
+            .map(Output::File)
+            .map_err(|err| OpenError(name.to_string(), err))
+    }
+
+    /// Creates a temporary file in the directory of `path`, with the permissions of the file
+    /// it will replace, if any.
+    fn atomic(path: PathBuf) -> Result<Self> {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        let name = path.file_name().map_or(String::from("output"), |name| name.to_string_lossy().into_owned());
+        for attempt in 0.. {
+            let temp = dir.join(format!(".{}.crab-{}-{}", name, process::id(), attempt));
+            match OpenOptions::new().write(true).create_new(true).open(&temp) {
+                Ok(file) => {
+                    if let Ok(meta) = std::fs::metadata(&path) {
+                        file.set_permissions(meta.permissions())?;
+                    }
+                    return Ok(Output::Atomic { file, temp, path });
+                }
+                Err(err) if err.kind() == ErrorKind::AlreadyExists => continue,
+                Err(err) => return Err(err),
+            }
+        }
+        unreachable!()
+    }
+
+    /// Returns whether the output goes to standard output.
+    pub fn is_stdout(&self) -> bool {
+        matches!(self, Output::Stdout)
+    }
+
+    /// Returns the device and inode of the output when it is a regular file, so inputs that
+    /// are the same file can be refused.
+    ///
+    /// Only regular files can be both read and appended to by the same process, so
+    /// pipes, terminals and devices never trigger the input-is-output check.
+    #[cfg(unix)]
+    pub fn identity(&self) -> Option<(u64, u64)> {
+        let file = match self {
+            Output::Stdout => File::from(stdout().as_fd().try_clone_to_owned().ok()?),
+            Output::File(file) | Output::Atomic { file, .. } => file.try_clone().ok()?,
+        };
+        let meta = file.metadata().ok()?;
+        meta.is_file().then(|| (meta.dev(), meta.ino()))
+    }
+
+    #[cfg(not(unix))]
+    pub fn identity(&self) -> Option<(u64, u64)> {
+        None
+    }
+
+    /// Returns a handle writing to the output, which can be moved to another thread.
+    pub fn writer(&self) -> Result<Box<dyn Write + Send>> {
+        Ok(match self {
+            Output::Stdout => Box::new(stdout()),
+            Output::File(file) | Output::Atomic { file, .. } => Box::new(file.try_clone()?),
+        })
+    }
+
+    /// Completes the output once everything has been written through its handles. A temporary
+    /// file is synced to disk and renamed over the file it replaces.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if syncing or renaming the temporary file fails, in which case it is
+    /// removed and the original file is left as it was.
+    pub fn commit(mut self) -> Result<()> {
+        let Output::Atomic { file, temp, path } = &mut self else {
+            return Ok(());
+        };
+        file.sync_all()?;
+        std::fs::rename(&*temp, &*path)?;
+        // The temporary file is gone, there is nothing left to clean up.
+        *temp = PathBuf::new();
+        sync_dir(path)
+    }
+}
+
+impl Drop for Output {
+    /// Removes the temporary file of an output that was never committed.
+    fn drop(&mut self) {
+        if let Output::Atomic { temp, .. } = self {
+            if !temp.as_os_str().is_empty() {
+                let _ = std::fs::remove_file(temp);
+            }
+        }
+    }
+}
+
+/// Returns whether `path` is the same file as one of the inputs, or as standard input. If the
+/// inputs are not known, it may be any regular file.
+#[cfg(unix)]
+fn is_input(path: &Path, inputs: Option<&[String]>) -> bool {
+    let Ok(output) = std::fs::metadata(path) else {
+        return false;
+    };
+    let Some(inputs) = inputs else {
+        return output.is_file();
+    };
+    let same = |meta: std::fs::Metadata| (meta.dev(), meta.ino()) == (output.dev(), output.ino());
+    let stdin = std::io::stdin().as_fd().try_clone_to_owned().ok().and_then(|fd| File::from(fd).metadata().ok());
+    stdin.is_some_and(same)
+        || inputs.iter().filter(|input| *input != "-").any(|input| std::fs::metadata(input).is_ok_and(same))
+}
+
+#[cfg(not(unix))]
+fn is_input(_path: &Path, _inputs: Option<&[String]>) -> bool {
+    false
+}
+
+/// Syncs the directory holding `path`, so a rename into it survives a crash.
+#[cfg(unix)]
+fn sync_dir(path: &Path) -> Result<()> {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => File::open(dir)?.sync_all(),
+        _ => File::open(".")?.sync_all(),
+    }
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_path: &Path) -> Result<()> {
+    Ok(())
+}
@@ -0,0 +1,245 @@
+use crate::format::{FormatOptions, FormatState};
+use crate::reader::SourceWriter;
+use std::collections::BTreeMap;
+use std::io::{BufWriter, Error, ErrorKind, Result, Write};
+use std::mem;
+use std::sync::mpsc::{channel, sync_channel, Receiver, Sender, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+/// The amount of input formatted by a worker at a time.
+pub const CHUNK_SIZE: usize = 1024 * 1024;
+
+/// The number of chunks per worker thread that may be handed out but not yet written, which
+/// bounds the memory used when the output is slower than the formatting.
+const CHUNKS_IN_FLIGHT: usize = 4;
+
+/// A chunk of input to be formatted, starting from a known state.
+struct Job {
+    /// The position of the chunk in the output.
+    index: usize,
+    /// The raw input bytes.
+    input: Vec<u8>,
+    /// The formatting state at the first byte of the chunk.
+    state: FormatState,
+}
+
+/// A message for the output thread, tagged with its position in the output.
+enum Output {
+    /// A formatted chunk, to be written to the output.
+    Chunk(Vec<u8>),
+    /// A request to flush the output and acknowledge once everything before it is written.
+    Flush(SyncSender<()>),
+}
+
+/// A formatting writer that spreads the work over several threads.
+///
+/// The input is cut into chunks. Before a chunk is handed to the worker pool, the state the
+/// formatting will be in at its first byte (line number, line start, blank line status and any
+/// held back carriage return)
+/// is computed by scanning the chunk without formatting it. The workers then format chunks in
+/// parallel, and a dedicated thread writes the results to the output in their original order.
+/// No more than `CHUNKS_IN_FLIGHT` chunks per worker are handed out before the output thread
+/// has written them, so a slow output slows down the input instead of piling up in memory.
+pub struct Pipeline {
+    /// The input not yet handed to the workers.
+    pending: Vec<u8>,
+    /// The formatting state at the end of the input handed out so far.
+    state: FormatState,
+    /// The index of the next chunk or flush request.
+    next_index: usize,
+    /// The channel feeding the worker pool.
+    jobs: Option<SyncSender<Job>>,
+    /// The channel feeding the output thread, used for flush requests.
+    outputs: Sender<(usize, Output)>,
+    /// The number of chunks handed out that the output thread may not have written yet.
+    in_flight: usize,
+    /// The most chunks that may be in flight at once.
+    max_in_flight: usize,
+    /// The channel the output thread acknowledges every written chunk on.
+    written: Receiver<()>,
+    /// The worker threads.
+    workers: Vec<JoinHandle<()>>,
+    /// The output thread, which returns the first error it met while writing.
+    output: Option<JoinHandle<Result<()>>>,
+}
+
+impl Pipeline {
+    /// Creates a new `Pipeline` with the given number of worker threads, formatting with the given
+    /// flags and writing to `writer`.
+    pub fn new(threads: usize, options: FormatOptions, writer: Box<dyn Write + Send>) -> Self {
+        let threads = threads.max(1);
+        let (jobs, job_receiver) = sync_channel::<Job>(threads * 2);
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+        let (outputs, output_receiver) = channel();
+        let (written_sender, written) = channel();
+
+        let workers = (0..threads)
+            .map(|_| {
+                let jobs = Arc::clone(&job_receiver);
+                let outputs = outputs.clone();
+                thread::spawn(move || Self::work(jobs, outputs))
+            })
+            .collect();
+        let output = thread::spawn(move || Self::write_in_order(output_receiver, written_sender, writer));
+
+        Pipeline {
+            pending: Vec::with_capacity(CHUNK_SIZE),
+            state: FormatState::new(options),
+            next_index: 0,
+            jobs: Some(jobs),
+            outputs,
+            in_flight: 0,
+            max_in_flight: threads * CHUNKS_IN_FLIGHT,
+            written,
+            workers,
+            output: Some(output),
+        }
+    }
+
+    /// Formats chunks from the job queue until it is closed.
+    fn work(jobs: Arc<Mutex<Receiver<Job>>>, outputs: Sender<(usize, Output)>) {
+        loop {
+            // Hold the lock only while taking a job, so the other workers can take the next one.
+            let job = match jobs.lock().unwrap().recv() {
+                Ok(job) => job,
+                Err(_) => return,
+            };
+            let Job { index, input, mut state } = job;
+            let mut formatted = Vec::with_capacity(input.len() + input.len() / 4);
+            // Writing to a vector cannot fail.
+            state.format(&input, &mut formatted).unwrap();
+            if outputs.send((index, Output::Chunk(formatted))).is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Writes formatted chunks to `writer` in the order of their indices, acknowledging each one
+    /// on `written` once it has been written.
+    fn write_in_order(
+        outputs: Receiver<(usize, Output)>,
+        written: Sender<()>,
+        writer: Box<dyn Write + Send>,
+    ) -> Result<()> {
+        let mut writer = BufWriter::new(writer);
+        let mut waiting = BTreeMap::new();
+        let mut next = 0;
+        for (index, output) in outputs {
+            waiting.insert(index, output);
+            // Write every message that is next in line.
+            while let Some(output) = waiting.remove(&next) {
+                match output {
+                    Output::Chunk(formatted) => {
+                        writer.write_all(&formatted)?;
+                        let _ = written.send(());
+                    }
+                    Output::Flush(done) => {
+                        writer.flush()?;
+                        let _ = done.send(());
+                    }
+                }
+                next += 1;
+            }
+        }
+        writer.flush()
+    }
+
+    /// Hands the pending input to the worker pool as a new chunk.
+    fn dispatch(&mut self) -> Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        self.reserve()?;
+        let input = mem::replace(&mut self.pending, Vec::with_capacity(CHUNK_SIZE));
+        // Compute the state at the end of this chunk, which is where the next one starts.
+        let state = self.state.clone();
+        self.state.skip(&input);
+        let job = Job { index: self.next_index, input, state };
+        self.next_index += 1;
+        match &self.jobs {
+            Some(jobs) if jobs.send(job).is_ok() => Ok(()),
+            _ => Err(self.output_error()),
+        }
+    }
+
+    /// Waits until a chunk can be handed out without going over the limit of chunks in flight.
+    fn reserve(&mut self) -> Result<()> {
+        while self.in_flight >= self.max_in_flight {
+            if self.written.recv().is_err() {
+                return Err(self.output_error());
+            }
+            self.in_flight -= 1;
+        }
+        self.in_flight += 1;
+        Ok(())
+    }
+
+    /// Stops the pipeline and returns the error that made the output thread stop.
+    fn output_error(&mut self) -> Error {
+        self.jobs = None;
+        match self.output.take().map(JoinHandle::join) {
+            Some(Ok(Err(err))) => err,
+            _ => Error::new(ErrorKind::BrokenPipe, "the output thread stopped"),
+        }
+    }
+}
+
+impl Write for Pipeline {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let n = buf.len().min(CHUNK_SIZE - self.pending.len());
+        self.pending.extend_from_slice(&buf[..n]);
+        if self.pending.len() == CHUNK_SIZE {
+            self.dispatch()?;
+        }
+        Ok(n)
+    }
+
+    /// Hands out any pending input and waits until everything has been written to the output.
+    fn flush(&mut self) -> Result<()> {
+        self.dispatch()?;
+        let (done, wait) = sync_channel(1);
+        let index = self.next_index;
+        self.next_index += 1;
+        if self.outputs.send((index, Output::Flush(done))).is_err() || wait.recv().is_err() {
+            return Err(self.output_error());
+        }
+        Ok(())
+    }
+}
+
+impl SourceWriter for Pipeline {
+    /// Writes whatever the formatting still holds back at the end of the input, and waits
+    /// until everything has been written to the output.
+    fn finish(&mut self) -> Result<()> {
+        self.dispatch()?;
+        let mut held = vec![];
+        self.state.finish(&mut held)?;
+        if !held.is_empty() {
+            self.reserve()?;
+            let index = self.next_index;
+            self.next_index += 1;
+            if self.outputs.send((index, Output::Chunk(held))).is_err() {
+                return Err(self.output_error());
+            }
+        }
+        self.flush()
+    }
+}
+
+impl Drop for Pipeline {
+    /// Writes whatever is left and waits for every thread to finish.
+    fn drop(&mut self) {
+        let _ = self.dispatch();
+        // Closing the job queue stops the workers, and once they are gone the output thread stops.
+        self.jobs = None;
+        for worker in mem::take(&mut self.workers) {
+            let _ = worker.join();
+        }
+        let (outputs, _) = channel();
+        drop(mem::replace(&mut self.outputs, outputs));
+        if let Some(output) = self.output.take() {
+            let _ = output.join();
+        }
+    }
+}
@@ -1,168 +1,705 @@
-use crate::errors::CrabError::{OpenError, ReadError};
+mod archive;
+mod http;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+mod uring;
+
+use crate::errors::{CrabError, OnError};
+#[cfg(unix)]
+use crate::mmap::MappedFile;
+use crate::errors::CrabError::{InputIsOutput, OpenError, ReadError};
 use atty::Stream;
 use std::fs::{File};
-use std::io::{BufReader, Read, Result, stdin};
-use std::path::PathBuf;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Result, stdin, Write};
+use std::io::{stdout, StdoutLock};
+#[cfg(unix)]
+use std::io::Seek;
+#[cfg(unix)]
+use std::os::fd::AsFd;
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+#[cfg(unix)]
+use std::fs::OpenOptions;
+#[cfg(unix)]
+use std::os::fd::FromRawFd;
+#[cfg(unix)]
+use std::os::unix::fs::{FileTypeExt, OpenOptionsExt};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::borrow::Cow;
+#[cfg(unix)]
+use std::ffi::OsString;
+#[cfg(unix)]
+use std::os::unix::ffi::OsStringExt;
+use std::path::{Path, PathBuf};
+#[cfg(unix)]
+use std::sync::mpsc;
+#[cfg(unix)]
+use std::thread;
+use std::time::Duration;
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 /// Represents a source of input.
 pub enum InputSource {
     /// Standard input.
     Stdin,
-    /// A file.
-    File(String),
+    /// A file. Its path need not be valid UTF-8 when it comes from `--files-from`.
+    File(PathBuf),
+    /// A member of a tar or zip archive, named as `archive!member`.
+    Member(String),
+    /// The names of the members of the tar or zip archive at the path.
+    Listing(String),
+    /// A Unix domain socket, named as `unix:/path/to.sock`, read until the peer closes it.
+    Socket(String),
+    /// An HTTP or HTTPS URL, whose response body is streamed as it is downloaded.
+    Url(String),
+    /// A file descriptor the process inherited, named as `fd:N`, `/dev/fd/N` or `/dev/stdin`.
+    /// It is read from where it stands, without being reopened.
+    Descriptor(String, i32),
+}
+
+impl InputSource {
+    /// Creates the input source an operand names: `-` for standard input, `archive!member`
+    /// for a member of a tar or zip archive, `unix:/path` for a Unix domain socket, `http://`
+    /// or `https://` for a URL, `fd:N`, `/dev/fd/N` or `/dev/stdin` for an inherited file
+    /// descriptor, or a file otherwise. A file whose name happens to look like one of these is still read as a file.
+    pub fn parse(name: String) -> Self {
+        if name == "-" {
+            InputSource::Stdin
+        } else if let Some(fd) = descriptor_path(&name) {
+            InputSource::Descriptor(name, fd)
+        } else if PathBuf::from(&name).exists() {
+            InputSource::File(PathBuf::from(name))
+        } else if let Some(fd) = name.strip_prefix(DESCRIPTOR_PREFIX).and_then(descriptor) {
+            InputSource::Descriptor(name, fd)
+        } else if archive::split(&name).is_some() {
+            InputSource::Member(name)
+        } else if name.starts_with(SOCKET_PREFIX) {
+            InputSource::Socket(name)
+        } else if http::is_url(&name) {
+            InputSource::Url(name)
+        } else {
+            InputSource::File(PathBuf::from(name))
+        }
+    }
+
+    /// Turns a file into the listing of its members, for `--list`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `OpenError` if the source is not a tar or zip archive.
+    pub fn into_listing(self) -> std::result::Result<Self, CrabError> {
+        match self {
+            InputSource::File(path) if path.to_str().is_some_and(archive::is_archive) => {
+                Ok(InputSource::Listing(path.to_string_lossy().into_owned()))
+            }
+            source => Err(OpenError(
+                source.name().to_string(),
+                Error::new(ErrorKind::InvalidInput, "not a tar or zip archive"),
+            )),
+        }
+    }
+
+    /// Returns the name of the source as it was given, `-` for standard input. Bytes of a
+    /// path that are not valid UTF-8 are replaced with U+FFFD.
+    pub fn name(&self) -> Cow<'_, str> {
+        match self {
+            InputSource::Stdin => Cow::Borrowed("-"),
+            InputSource::File(path) => path.to_string_lossy(),
+            InputSource::Member(name)
+            | InputSource::Listing(name)
+            | InputSource::Socket(name)
+            | InputSource::Url(name)
+            | InputSource::Descriptor(name, _) => Cow::Borrowed(name),
+        }
+    }
+}
+
+/// The prefix of the operands that name a Unix domain socket.
+const SOCKET_PREFIX: &str = "unix:";
+
+/// The prefix of the operands that name an inherited file descriptor.
+const DESCRIPTOR_PREFIX: &str = "fd:";
+
+/// The size of the buffer used to read each input, the same as GNU cat.
+const BUFFER_SIZE: usize = 128 * 1024;
+
+/// An input source that has been opened and is ready to be read.
+enum Input {
+    /// Standard input, read through the shared stdin handle.
+    Stdin,
+    /// A file opened for reading.
+    File(File),
+    /// A large regular file mapped into memory, written straight from the mapping.
+    #[cfg(unix)]
+    Mapped(MappedFile),
+    /// Data produced on the fly, such as an archive member being decompressed.
+    Stream(Box<dyn Read>),
+}
+
+impl Read for Input {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        match self {
+            Input::Stdin => stdin().lock().read(buf),
+            Input::File(file) => file.read(buf),
+            Input::Stream(stream) => stream.read(buf),
+            // Mapped files are handed to the writer as a whole by `copy_to`, never read.
+            #[cfg(unix)]
+            Input::Mapped(_) => Ok(0),
+        }
+    }
+}
+
+/// A writer that is told where the data of each input source begins and ends.
+pub trait SourceWriter: Write {
+    /// Called before the data of a source is written.
+    fn start_source(&mut self, _source: &InputSource) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called after the last data of a source has been written.
+    fn end_source(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called once all sources have been written, to write whatever is still held back and flush.
+    fn finish(&mut self) -> Result<()> {
+        self.flush()
+    }
+}
+
+impl SourceWriter for StdoutLock<'_> {}
+
+impl SourceWriter for Box<dyn Write + Send> {}
+
+impl SourceWriter for Box<dyn SourceWriter> {
+    fn start_source(&mut self, source: &InputSource) -> Result<()> {
+        (**self).start_source(source)
+    }
+
+    fn end_source(&mut self) -> Result<()> {
+        (**self).end_source()
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        (**self).finish()
+    }
 }
 
 /// Represents a reader that can read input from multiple sources.
 pub struct Reader {
-    /// The list of input sources to read from.
-    sources: Vec<InputSource>,
-    /// The list of file readers, corresponding to the `File` input sources.
-    readers: Vec<BufReader<File>>,
+    /// The input sources still to be read. Each one is opened only when the previous one is exhausted.
+    sources: Box<dyn Iterator<Item = std::result::Result<InputSource, CrabError>>>,
+    /// The device and inode of every output that is a regular file.
+    outputs: Vec<(u64, u64)>,
+    /// Whether large regular files are mapped into memory instead of read.
+    mmap: bool,
+    /// How long to wait for a writer to open a FIFO, or `None` to wait as long as it takes.
+    fifo_timeout: Option<Duration>,
+    /// The HTTP client URLs are downloaded with.
+    agent: ureq::Agent,
+    /// The io_uring instance used to open and read files, if the kernel supports it.
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    ring: Option<uring::Ring>,
+    /// What to do when a source cannot be opened or read.
+    on_error: OnError,
+    /// Whether sources that do not exist are skipped as if they were empty.
+    missing_ok: bool,
+    /// The errors reported so far.
+    pub failures: Vec<CrabError>,
+    /// Whether reading stopped at an error, because of `OnError::Abort`.
+    aborted: bool,
     /// The exit code to return if an error occurs while reading.
     pub exit_code: i32,
 }
 
-impl Read for Reader {
-    /// Attempts to read data from the input sources into the given buffer.
+impl Reader {
+    /// Create a new `Reader` instance.
+    ///
+    /// This method takes an iterator of `InputSource` enums as input, which represent
+    /// the sources from which data will be read. The sources are consumed lazily, so
+    /// only one of them is open at any time. Errors yielded by the iterator are reported
+    /// when they are reached.
     ///
-    /// This method reads from the first input source in the list that contains data,
-    /// and removes that source from the list once it has been exhausted.
-    /// If an error occurs while reading, the source is removed from the list
-    /// and an error message is printed to the standard error stream.
+    /// # Arguments
+    ///
+    /// * `sources` - An iterator of `InputSource` enums representing the sources to read from.
+    /// * `outputs` - The device and inode of every output that is a regular file. Sources that
+    ///   are one of these files are refused, since reading them would never reach the end.
+    /// * `mmap` - Whether large regular files should be mapped into memory, so the writer
+    ///   receives their contents without an intermediate copy.
+    /// * `fifo_timeout` - How long to wait for a writer to open a FIFO before reporting it, or
+    ///   `None` to wait forever like GNU cat.
+    /// * `http_timeout` - How long to wait for a server to accept a connection, to answer a
+    ///   request and to send more of the body, or `None` to wait as long as it takes.
+    /// * `on_error` - What to do when a source cannot be opened or read.
+    /// * `missing_ok` - Whether sources that do not exist are skipped silently, as if empty.
+    ///
+    /// # Returns
+    ///
+    /// A new `Reader` instance.
+    pub fn new<I>(
+        sources: I,
+        outputs: Vec<(u64, u64)>,
+        mmap: bool,
+        fifo_timeout: Option<Duration>,
+        http_timeout: Option<Duration>,
+        on_error: OnError,
+        missing_ok: bool,
+    ) -> Self
+    where
+        I: Iterator<Item = std::result::Result<InputSource, CrabError>> + 'static,
+    {
+        Reader {
+            sources: Box::new(sources),
+            outputs,
+            mmap,
+            fifo_timeout,
+            agent: http::agent(http_timeout),
+            // The ring opens files ahead of their turn, which must not happen once an error
+            // has stopped the copy.
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            ring: if on_error == OnError::Abort { None } else { uring::Ring::new() },
+            on_error,
+            missing_ok,
+            failures: vec![],
+            aborted: false,
+            exit_code: 0,
+        }
+    }
+
+    /// Only take the next source from `sources` once the previous one has been copied, instead
+    /// of opening a batch of them ahead with io_uring. This is needed when the sources are
+    /// produced as they are taken, like the names read from `--files-from`.
+    pub fn read_in_turn(&mut self) {
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        {
+            self.ring = None;
+        }
+    }
+
+    /// Copy the data of every input source, in order, to the given writer.
+    ///
+    /// Each source is opened just before it is read and closed as soon as it is exhausted.
+    /// The writer is told where each source that could be opened begins and ends.
+    /// The writer is flushed at the end of every source, whenever standard input has no more
+    /// data ready, and before an error is printed, so errors show up at the same point of the
+    /// stream as they do with GNU cat. Sources that fail are handled as `on_error` says: by
+    /// default they are skipped and set the exit code to 1.
+    ///
+    /// # Arguments
+    ///
+    /// * `writer` - The writer that receives the data.
     ///
     /// # Errors
     ///
-    /// Returns an error if any of the input sources cannot be read from,
-    /// or if an I/O error occurs while reading.
-    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
-        let mut r = 0;
-
-        // Loop until data is read or there are no more input sources.
-        while r == 0 && !self.sources.is_empty() {
-            match &self.sources[0] {
-                InputSource::File(_) => {
-                    // If the current input source is a file, read from it.
-                    match self.readers[0].read(buf) {
-                        Ok(n) => {
-                            r = n;
-                        }
-                        Err(err) => {
-                            // If an error occurs, remove the source and print an error message.
-                            ReadError(self.sources[0].clone(), err).show();
-                            self.sources.remove(0);
-                            self.readers.remove(0);
-                            continue;
-                        }
-                    }
+    /// Returns an error if writing to or flushing the writer fails.
+    pub fn copy_to<W: SourceWriter>(&mut self, writer: &mut W) -> Result<()> {
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        if let Some(mut ring) = self.ring.take() {
+            let result = self.copy_batched(&mut ring, writer, false);
+            self.ring = Some(ring);
+            return result;
+        }
 
-                    if r == 0 {
-                        // If no data was read, remove the source.
-                        self.sources.remove(0);
-                        self.readers.remove(0);
-                    }
+        let mut buf = vec![0; BUFFER_SIZE];
+        while !self.aborted {
+            let Some(next) = self.sources.next() else {
+                break;
+            };
+            // Open the next source, reporting it if it cannot be opened.
+            let (source, mut input) = match next.and_then(|source| self.open(source)) {
+                Ok(Some(opened)) => opened,
+                Ok(None) => continue,
+                Err(err) => {
+                    self.fail(writer, err)?;
+                    continue;
                 }
-                InputSource::Stdin => {
-                    // If the current input source is stdin, check if it is a TTY.
-                    if atty::isnt(Stream::Stdin) {
-                        // If it is not a TTY, read from it.
-                        match stdin().lock().read(buf) {
-                            Ok(n) => {r=n}
-                            Err(err) => {
-                                // If an error occurs, remove the source and print an error message.
-                                ReadError(self.sources[0].clone(), err).show();
-                                self.sources.remove(0);
-                                continue;
-                            }
-                        }
-                        if r == 0 {
-                            // If no data was read, remove the source.
-                            self.sources.remove(0);
-                        }
-                    } else {
-                        // If it is a TTY, remove the source.
-                        self.sources.remove(0);
+            };
+
+            writer.start_source(&source)?;
+            self.copy_input(&source, &mut input, &mut buf, writer)?;
+            // Close the source before moving to the next one.
+            drop(input);
+            writer.end_source()?;
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Copy the data of every input source, in order, to standard output.
+    ///
+    /// With the io_uring backend, the data is written from the buffers it was read into,
+    /// without being copied. Otherwise this is the same as `copy_to` with standard output.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing to standard output fails.
+    pub fn copy_to_stdout(&mut self) -> Result<()> {
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        if let Some(mut ring) = self.ring.take() {
+            let result = self.copy_batched(&mut ring, &mut stdout().lock(), true);
+            self.ring = Some(ring);
+            return result;
+        }
+        self.copy_to(&mut stdout().lock())
+    }
+
+    /// Copy the data of a single opened input to the given writer, reporting read errors.
+    fn copy_input<W: Write>(&mut self, source: &InputSource, input: &mut Input, buf: &mut [u8], writer: &mut W) -> Result<()> {
+        // Mapped files are written in place, without going through the buffer.
+        #[cfg(unix)]
+        if let Input::Mapped(mapped) = input {
+            if !mapped.write_to(writer)? {
+                let err = Error::new(ErrorKind::UnexpectedEof, "file truncated while reading");
+                self.fail(writer, CrabError::read(source.clone(), err))?;
+            }
+            return Ok(());
+        }
+
+        loop {
+            match input.read(buf) {
+                // If no data was read, the source is exhausted.
+                Ok(0) => return Ok(()),
+                Ok(n) => {
+                    writer.write_all(&buf[..n])?;
+                    // A short read from stdin means no more data is ready, so show what we have.
+                    if n < buf.len() && matches!(input, Input::Stdin) {
+                        writer.flush()?;
                     }
                 }
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                // If an error occurs, report it and stop reading this source.
+                Err(err) => return self.fail(writer, CrabError::read(source.clone(), err)),
             }
         }
+    }
 
-        // Return the number of bytes read.
-        Ok(r)
+    /// Handle an error as `on_error` says. Unless it is ignored, it is reported after flushing
+    /// everything written so far, and sets the exit code to 1. Missing sources are not errors
+    /// with `missing_ok`.
+    fn fail<W: Write>(&mut self, writer: &mut W, err: CrabError) -> Result<()> {
+        if self.on_error == OnError::Ignore || (self.missing_ok && err.is_missing()) {
+            return Ok(());
+        }
+        writer.flush()?;
+        err.show();
+        self.failures.push(err);
+        self.aborted = self.on_error == OnError::Abort;
+        self.exit_code = 1;
+        Ok(())
     }
-}
 
-impl Reader {
-    /// Create a new `Reader` instance.
+    /// Open an input source so it can be read.
     ///
-    /// This method takes a vector of `InputSource` enums as input, which represent
-    /// the sources from which data will be read. The method creates a new `Reader`
+    /// Standard input is skipped when it is a TTY.
     ///
     /// # Arguments
     ///
-    /// * `s` - A vector of `InputSource` enums representing the sources to read from.
+    /// * `source` - The input source to open.
     ///
     /// # Returns
     ///
-    /// A new `Reader` instance.
-    pub fn new(s: Vec<InputSource>) -> Self {
-        let mut exit_code = 0;
-        let mut sources = vec![];
-        // Create a new Vec of BufReaders by iterating over each InputSource in the input vector
-        // and opening a corresponding file, if applicable.
-        let readers = s.iter()
-            .map(|i| match i {
-                InputSource::File(file) => {
-                    // Open the file and return a BufReader for valid files
-                    if let Some(_file) = Self::open_file(file.to_string()) {
-                        // Push the valid source to the sources vec
-                        (&mut sources).push(i.clone());
-                        Some(BufReader::new(_file))
-                    } else {
-                        // Set the exit code to 1 if the file could not be opened
-                        exit_code = 1;
-                        None
-                    }
-                }
-                InputSource::Stdin => {
-                    // Push the valid source to the sources vec
-                    (&mut sources).push(i.clone());
-                    None
-                }
-            })
-            .filter(|o| matches!(o, Some(_)))
-            .map(|o| o.unwrap())
-            .collect::<Vec<_>>();
-        Reader {
-            sources,
-            readers,
-            exit_code,
+    /// The source and its opened input, or `None` if it should be skipped.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the source cannot be opened or is the same file as the output.
+    fn open(&self, source: InputSource) -> std::result::Result<Option<(InputSource, Input)>, CrabError> {
+        let input = match &source {
+            InputSource::File(path) => {
+                let file = Self::open_file(path, &self.outputs, self.fifo_timeout)?;
+                self.map_file(file)
+            }
+            InputSource::Member(name) => {
+                let (path, member) = archive::split(name).expect("member names are checked when parsed");
+                let stream = archive::open_member(path, member).map_err(|err| OpenError(name.clone(), err))?;
+                Input::Stream(stream)
+            }
+            InputSource::Listing(path) => Input::Stream(archive::list(path).map_err(|err| OpenError(path.clone(), err))?),
+            InputSource::Socket(name) => Input::Stream(connect(&name[SOCKET_PREFIX.len()..]).map_err(|err| OpenError(name.clone(), err))?),
+            InputSource::Url(url) => Input::Stream(http::get(&self.agent, url)?),
+            // Standard input is shared with `-`, so both read from the same buffer.
+            InputSource::Descriptor(_, 0) if atty::is(Stream::Stdin) => return Ok(None),
+            InputSource::Descriptor(name, 0) if is_output(duplicate_stdin(), &self.outputs) => {
+                return Err(InputIsOutput(name.clone()));
+            }
+            InputSource::Descriptor(_, 0) => Input::Stdin,
+            // Other descriptors are never mapped, since a mapping would start at the beginning
+            // of the file rather than where the descriptor stands.
+            InputSource::Descriptor(name, fd) => match duplicate(*fd) {
+                Ok(file) if is_output(file.try_clone(), &self.outputs) => return Err(InputIsOutput(name.clone())),
+                Ok(file) => Input::File(file),
+                Err(err) => return Err(OpenError(name.clone(), err)),
+            },
+            // If stdin is a TTY, skip it.
+            InputSource::Stdin if atty::is(Stream::Stdin) => return Ok(None),
+            // Refuse to read stdin when it is the same file stdout writes to
+            InputSource::Stdin if is_output(duplicate_stdin(), &self.outputs) => {
+                return Err(InputIsOutput(String::from("-")));
+            }
+            InputSource::Stdin => Input::Stdin,
+        };
+        Ok(Some((source, input)))
+    }
+
+    /// Map a file into memory if mapping is enabled and the file is large enough, or
+    /// fall back to reading it when it cannot be mapped.
+    #[cfg(unix)]
+    fn map_file(&self, file: File) -> Input {
+        if !self.mmap {
+            return Input::File(file);
         }
+        match MappedFile::map(file) {
+            Ok(mapped) => Input::Mapped(mapped),
+            Err(file) => Input::File(file),
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn map_file(&self, file: File) -> Input {
+        Input::File(file)
     }
 
     /// Open a file and return a File handle.
     ///
     /// This method takes a string representing a file path, and returns a `File` handle
-    /// for the file at that path, or `None` if the file could not be opened. If an error
-    /// occurs while opening the file, the method prints an error message using the `OpenError`
-    /// struct and returns `None`.
+    /// for the file at that path. Files that are the same as the output are rejected
+    /// with an `InputIsOutput` error, since reading them would never reach the end.
+    /// A FIFO is only handed back once a writer has opened it; if that takes longer than
+    /// `fifo_timeout`, it is reported instead of waiting forever.
     ///
     /// # Arguments
     ///
-    /// * `_path` - A string representing the path of the file to open.
+    /// * `path` - The path of the file to open.
+    /// * `outputs` - The device and inode of every output that is a regular file.
+    /// * `fifo_timeout` - How long to wait for a writer of a FIFO, or `None` to wait forever.
     ///
     /// # Returns
     ///
-    /// A `File` handle for the opened file, or `None` if the file could not be opened.
-    fn open_file(_path: String) -> Option<File> {
-        let path: PathBuf = PathBuf::from(&_path);
-        match File::open(path) {
-            Ok(file) => Some(file),
-            Err(err) => {
-                // Print an error message if the file could not be opened
-                OpenError(_path, err).show();
-                None
+    /// A `File` handle for the opened file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `OpenError` if the file could not be opened.
+    fn open_file(path: &Path, outputs: &[(u64, u64)], fifo_timeout: Option<Duration>) -> std::result::Result<File, CrabError> {
+        let opened = if is_fifo(path) { open_fifo(path, fifo_timeout) } else { File::open(path) };
+        let name = || path.to_string_lossy().into_owned();
+        match opened {
+            Ok(file) if is_output(file.try_clone(), outputs) => Err(InputIsOutput(name())),
+            Ok(file) => Ok(file),
+            Err(err) => Err(OpenError(name(), err)),
+        }
+    }
+}
+
+/// Streams input sources from a list of file names, such as the output of `find -print0`.
+///
+/// The list is opened on the first call to `next` and read one entry at a time, so it can
+/// hold more names than fit on a command line. An entry of `-` means standard input.
+pub struct SourceList {
+    /// The path of the list, `-` for standard input.
+    path: String,
+    /// The byte that terminates each entry, either `\n` or `\0`.
+    delimiter: u8,
+    /// The opened list, or `None` before the first entry is read.
+    reader: Option<Box<dyn BufRead>>,
+    /// Whether the list has been exhausted or failed.
+    done: bool,
+}
+
+impl SourceList {
+    /// Creates a new `SourceList` reading entries terminated by `delimiter` from `path`.
+    pub fn new(path: String, delimiter: u8) -> Self {
+        SourceList { path, delimiter, reader: None, done: false }
+    }
+
+    /// Returns the list as an input source, used to report errors while reading it.
+    fn source(&self) -> InputSource {
+        if self.path == "-" { InputSource::Stdin } else { InputSource::File(PathBuf::from(&self.path)) }
+    }
+}
+
+impl Iterator for SourceList {
+    type Item = std::result::Result<InputSource, CrabError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        // Open the list the first time an entry is requested.
+        let reader = match &mut self.reader {
+            Some(reader) => reader,
+            None => {
+                let reader: Box<dyn BufRead> = if self.path == "-" {
+                    Box::new(BufReader::new(stdin()))
+                } else {
+                    match File::open(&self.path) {
+                        Ok(file) => Box::new(BufReader::new(file)),
+                        Err(err) => {
+                            self.done = true;
+                            return Some(Err(OpenError(self.path.clone(), err)));
+                        }
+                    }
+                };
+                self.reader.insert(reader)
+            }
+        };
+
+        loop {
+            let mut entry = vec![];
+            match reader.read_until(self.delimiter, &mut entry) {
+                Ok(0) => {
+                    self.done = true;
+                    return None;
+                }
+                Ok(_) => {
+                    if entry.last() == Some(&self.delimiter) {
+                        entry.pop();
+                    }
+                    // Skip empty entries, such as blank lines.
+                    if entry.is_empty() {
+                        continue;
+                    }
+                    return Some(match String::from_utf8(entry) {
+                        Ok(name) => Ok(InputSource::parse(name)),
+                        // A name that is not UTF-8 is a file, opened by its bytes as they are.
+                        #[cfg(unix)]
+                        Err(err) => Ok(InputSource::File(PathBuf::from(OsString::from_vec(err.into_bytes())))),
+                        #[cfg(not(unix))]
+                        Err(err) => Err(OpenError(
+                            String::from_utf8_lossy(err.as_bytes()).into_owned(),
+                            Error::new(ErrorKind::InvalidData, "invalid UTF-8 in file name"),
+                        )),
+                    });
+                }
+                Err(err) => {
+                    self.done = true;
+                    return Some(Err(ReadError(self.source(), err)));
+                }
             }
         }
     }
 }
+
+/// Connects to the Unix domain socket at `path`, to read what the peer sends until it closes
+/// the connection.
+#[cfg(unix)]
+fn connect(path: &str) -> Result<Box<dyn Read>> {
+    Ok(Box::new(UnixStream::connect(path)?))
+}
+
+#[cfg(not(unix))]
+fn connect(_path: &str) -> Result<Box<dyn Read>> {
+    Err(Error::new(ErrorKind::Unsupported, "Unix domain sockets are not supported on this platform"))
+}
+
+/// Returns the file descriptor a path names, if it is `/dev/fd/N`, `/proc/self/fd/N` or
+/// `/dev/stdin`. These paths exist, but the descriptor is read without being reopened.
+fn descriptor_path(name: &str) -> Option<i32> {
+    if name == "/dev/stdin" {
+        return Some(0);
+    }
+    // The paths of descriptors only exist on Unix.
+    if !cfg!(unix) {
+        return None;
+    }
+    name.strip_prefix("/dev/fd/").or_else(|| name.strip_prefix("/proc/self/fd/")).and_then(descriptor)
+}
+
+/// Parses the number of a file descriptor, as it follows `fd:` or `/dev/fd/`.
+fn descriptor(number: &str) -> Option<i32> {
+    number.parse().ok().filter(|fd| *fd >= 0)
+}
+
+/// Duplicates the inherited descriptor `fd`, so it can be read as a `File` and closed after
+/// without closing the original. The duplicate shares the offset of the original.
+#[cfg(unix)]
+fn duplicate(fd: i32) -> Result<File> {
+    // SAFETY: F_DUPFD_CLOEXEC only creates a new descriptor, and fails with EBADF if `fd` is
+    // not open.
+    match unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 0) } {
+        -1 => Err(Error::last_os_error()),
+        // SAFETY: the new descriptor is open and owned by nothing else.
+        duplicate => Ok(unsafe { File::from_raw_fd(duplicate) }),
+    }
+}
+
+#[cfg(not(unix))]
+fn duplicate(_fd: i32) -> Result<File> {
+    Err(Error::new(ErrorKind::Unsupported, "file descriptors are not supported on this platform"))
+}
+
+/// Returns whether `path` is a FIFO, also known as a named pipe.
+#[cfg(unix)]
+fn is_fifo(path: &Path) -> bool {
+    std::fs::metadata(path).is_ok_and(|meta| meta.file_type().is_fifo())
+}
+
+#[cfg(not(unix))]
+fn is_fifo(_path: &Path) -> bool {
+    false
+}
+
+/// Opens a FIFO, waiting up to `timeout` for a writer to open it too. Once one has, the FIFO
+/// is read as long as it takes, however slow the writer is.
+///
+/// # Errors
+///
+/// Returns a `TimedOut` error if no writer opens the FIFO within `timeout`.
+#[cfg(unix)]
+fn open_fifo(path: &Path, timeout: Option<Duration>) -> Result<File> {
+    let Some(timeout) = timeout else {
+        return File::open(path);
+    };
+    // Opening a FIFO for reading blocks until there is a writer, so it is done on another
+    // thread that can be given up on.
+    let (sender, receiver) = mpsc::channel();
+    let fifo = path.to_path_buf();
+    thread::spawn(move || sender.send(File::open(fifo)));
+    if let Ok(opened) = receiver.recv_timeout(timeout) {
+        return opened;
+    }
+    // The reader waiting on the other thread lets a writer in without blocking, which wakes
+    // it up, so the thread does not outlive the FIFO.
+    let _ = OpenOptions::new().write(true).custom_flags(libc::O_NONBLOCK).open(path);
+    let seconds = timeout.as_secs_f64();
+    Err(Error::new(ErrorKind::TimedOut, format!("no writer opened the FIFO after {} seconds", seconds)))
+}
+
+#[cfg(not(unix))]
+fn open_fifo(path: &Path, _timeout: Option<Duration>) -> Result<File> {
+    File::open(path)
+}
+
+/// Duplicates the standard input descriptor so it can be inspected as a `File`.
+#[cfg(unix)]
+fn duplicate_stdin() -> Result<File> {
+    Ok(File::from(stdin().as_fd().try_clone_to_owned()?))
+}
+
+#[cfg(not(unix))]
+fn duplicate_stdin() -> Result<File> {
+    Err(std::io::Error::from(std::io::ErrorKind::Unsupported))
+}
+
+/// Checks whether an input is one of the files the output is written to.
+///
+/// Like GNU cat, an input only counts when there is still data left to read from its current
+/// offset, so an empty file or one that was already consumed is accepted.
+#[cfg(unix)]
+fn is_output(input: Result<File>, outputs: &[(u64, u64)]) -> bool {
+    let (false, Ok(mut input)) = (outputs.is_empty(), input) else {
+        return false;
+    };
+    match input.metadata() {
+        Ok(meta) if outputs.contains(&(meta.dev(), meta.ino())) => input
+            .stream_position()
+            .is_ok_and(|offset| offset < meta.len()),
+        _ => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn is_output(_input: Result<File>, _outputs: &[(u64, u64)]) -> bool {
+    false
+}
@@ -0,0 +1,199 @@
+use flate2::read::{DeflateDecoder, GzDecoder};
+use std::fs::File;
+use std::io::{copy, sink, BufReader, Cursor, Error, ErrorKind, Read, Result, Seek, SeekFrom};
+use zip::{CompressionMethod, ZipArchive};
+
+/// The kinds of archives whose members can be read.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Format {
+    /// A plain tar archive, `.tar`.
+    Tar,
+    /// A gzip-compressed tar archive, `.tar.gz` or `.tgz`.
+    TarGz,
+    /// A zip archive, `.zip`.
+    Zip,
+}
+
+impl Format {
+    /// Returns the format of an archive from the extension of its path, if it is one.
+    fn of(path: &str) -> Option<Self> {
+        let path = path.to_ascii_lowercase();
+        if path.ends_with(".tar") {
+            Some(Format::Tar)
+        } else if path.ends_with(".tar.gz") || path.ends_with(".tgz") {
+            Some(Format::TarGz)
+        } else if path.ends_with(".zip") {
+            Some(Format::Zip)
+        } else {
+            None
+        }
+    }
+}
+
+/// Splits an operand such as `bundle.tar.gz!etc/config.yaml` into the path of the archive and
+/// the name of the member, at the first `!` that follows an archive extension.
+pub fn split(name: &str) -> Option<(&str, &str)> {
+    name.match_indices('!')
+        .map(|(at, _)| (&name[..at], &name[at + 1..]))
+        .find(|(archive, member)| Format::of(archive).is_some() && !member.is_empty())
+}
+
+/// Returns whether `path` names an archive that can be listed or read from.
+pub fn is_archive(path: &str) -> bool {
+    Format::of(path).is_some()
+}
+
+/// Opens the member `member` of the archive at `archive` and returns a reader of its contents.
+///
+/// Members are looked up by their name in the archive, where a leading `./` does not count.
+/// Only the member is read from a zip archive; a tar archive is read once, up to the end of
+/// the member, and decompressed on the way if it is compressed.
+///
+/// # Errors
+///
+/// Returns a `NotFound` error if the archive has no such member, and an `IsADirectory` error
+/// if the member is a directory.
+pub fn open_member(archive: &str, member: &str) -> Result<Box<dyn Read>> {
+    match Format::of(archive) {
+        Some(Format::Tar) => tar_member(archive, member, false),
+        Some(Format::TarGz) => tar_member(archive, member, true),
+        Some(Format::Zip) => zip_member(archive, member),
+        None => Err(not_an_archive()),
+    }
+}
+
+/// Returns a reader of the names of the members of the archive at `archive`, one per line.
+///
+/// # Errors
+///
+/// Returns an error if `archive` is not a tar or zip archive, or cannot be read.
+pub fn list(archive: &str) -> Result<Box<dyn Read>> {
+    let mut names = vec![];
+    match Format::of(archive) {
+        Some(format @ (Format::Tar | Format::TarGz)) => {
+            let mut tar = tar::Archive::new(tar_reader(archive, format == Format::TarGz)?);
+            for entry in tar.entries()? {
+                names.extend_from_slice(&entry?.path_bytes());
+                names.push(b'\n');
+            }
+        }
+        Some(Format::Zip) => {
+            let zip = ZipArchive::new(File::open(archive)?).map_err(zip_error)?;
+            for name in zip.file_names() {
+                names.extend_from_slice(name.as_bytes());
+                names.push(b'\n');
+            }
+        }
+        None => return Err(not_an_archive()),
+    }
+    Ok(Box::new(Cursor::new(names)))
+}
+
+/// Opens a tar archive, decompressing it if `gz` is set.
+fn tar_reader(archive: &str, gz: bool) -> Result<Box<dyn Read>> {
+    let file = BufReader::new(File::open(archive)?);
+    Ok(if gz { Box::new(GzDecoder::new(file)) } else { Box::new(file) })
+}
+
+/// A reader that counts the bytes read through it.
+struct Counted<R: Read> {
+    /// The reader.
+    inner: R,
+    /// The number of bytes read so far.
+    read: u64,
+}
+
+impl<R: Read> Read for Counted<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.read += read as u64;
+        Ok(read)
+    }
+}
+
+/// Finds a member of a tar archive and returns a reader of its contents.
+///
+/// The archive is read once: the headers are walked up to the member, then the entries,
+/// which borrow the archive, are let go of and its data is read on from the same stream.
+fn tar_member(archive: &str, member: &str, gz: bool) -> Result<Box<dyn Read>> {
+    let wanted = normalize(member.as_bytes());
+    let mut found = None;
+    let mut tar = tar::Archive::new(Counted { inner: tar_reader(archive, gz)?, read: 0 });
+    for entry in tar.entries()? {
+        let entry = entry?;
+        if normalize(&entry.path_bytes()) != wanted {
+            continue;
+        }
+        let kind = entry.header().entry_type();
+        if kind.is_dir() {
+            return Err(Error::new(ErrorKind::IsADirectory, "Is a directory"));
+        }
+        if !kind.is_file() {
+            return Err(Error::new(ErrorKind::InvalidInput, "not a regular file in the archive"));
+        }
+        found = Some((entry.raw_file_position(), entry.size()));
+        break;
+    }
+    let Some((position, size)) = found else {
+        return Err(no_such_member());
+    };
+
+    // The headers stop where the data of the member starts.
+    let Counted { mut inner, read } = tar.into_inner();
+    copy(&mut inner.by_ref().take(position.saturating_sub(read)), &mut sink())?;
+    Ok(Box::new(inner.take(size)))
+}
+
+/// Finds a member of a zip archive and returns a reader of its contents, inflating it if it
+/// is compressed.
+fn zip_member(archive: &str, member: &str) -> Result<Box<dyn Read>> {
+    let mut zip = ZipArchive::new(File::open(archive)?).map_err(zip_error)?;
+    if zip.index_for_name(member).is_none() && zip.index_for_name(&format!("{}/", member)).is_some() {
+        return Err(Error::new(ErrorKind::IsADirectory, "Is a directory"));
+    }
+    let index = zip.index_for_name(member).ok_or_else(no_such_member)?;
+    let entry = zip.by_index_raw(index).map_err(zip_error)?;
+    if entry.is_dir() {
+        return Err(Error::new(ErrorKind::IsADirectory, "Is a directory"));
+    }
+    if entry.encrypted() {
+        return Err(Error::new(ErrorKind::Unsupported, "encrypted archive members are not supported"));
+    }
+    let (start, size, method) = (entry.data_start(), entry.compressed_size(), entry.compression());
+    drop(entry);
+
+    let mut file = File::open(archive)?;
+    file.seek(SeekFrom::Start(start))?;
+    let data = BufReader::new(file).take(size);
+    if method == CompressionMethod::Stored {
+        Ok(Box::new(data))
+    } else if method == CompressionMethod::DEFLATE {
+        Ok(Box::new(DeflateDecoder::new(data)))
+    } else {
+        Err(Error::new(ErrorKind::Unsupported, format!("unsupported compression method {}", method)))
+    }
+}
+
+/// Returns the name of a member without a leading `./` or a trailing `/`.
+fn normalize(name: &[u8]) -> &[u8] {
+    let name = name.strip_prefix(b"./").unwrap_or(name);
+    name.strip_suffix(b"/").unwrap_or(name)
+}
+
+/// The error for a member that is not in its archive.
+fn no_such_member() -> Error {
+    Error::new(ErrorKind::NotFound, "No such member in archive")
+}
+
+/// The error for listing a file that is not a known kind of archive.
+fn not_an_archive() -> Error {
+    Error::new(ErrorKind::InvalidInput, "not a tar or zip archive")
+}
+
+/// Converts an error of the zip reader into an IO error.
+fn zip_error(err: zip::result::ZipError) -> Error {
+    match err {
+        zip::result::ZipError::Io(err) => err,
+        err => Error::new(ErrorKind::InvalidData, err.to_string()),
+    }
+}
@@ -0,0 +1,119 @@
+use crate::errors::CrabError::{self, HttpError, OpenError};
+use std::io::{Error, ErrorKind, Read};
+use std::time::Duration;
+use ureq::unversioned::resolver::DefaultResolver;
+use ureq::unversioned::transport::{Buffers, ConnectionDetails, Connector, DefaultConnector, NextTimeout, Transport};
+use ureq::Agent;
+
+/// The most redirects followed for a single URL.
+const MAX_REDIRECTS: u32 = 10;
+
+/// Returns whether an operand is an HTTP or HTTPS URL.
+pub fn is_url(name: &str) -> bool {
+    let scheme = name.split_once("://").map(|(scheme, _)| scheme.to_ascii_lowercase());
+    matches!(scheme.as_deref(), Some("http" | "https"))
+}
+
+/// Creates the agent that downloads every URL, so connections to the same host are reused.
+///
+/// `timeout` bounds connecting and waiting for the response headers. Reading the body can
+/// take as long as the download does, but `timeout` also bounds every wait for more of it,
+/// so a server that stops sending is reported instead of waited for forever.
+pub fn agent(timeout: Option<Duration>) -> Agent {
+    let config = Agent::config_builder()
+        .timeout_connect(timeout)
+        .timeout_recv_response(timeout)
+        .max_redirects(MAX_REDIRECTS)
+        .build();
+    match timeout {
+        Some(timeout) => Agent::with_parts(config, DefaultConnector::new().chain(IdleTimeout(timeout)), DefaultResolver::default()),
+        None => config.into(),
+    }
+}
+
+/// A connector that gives up on a connection when no data arrives for the given time.
+#[derive(Debug)]
+struct IdleTimeout(Duration);
+
+impl Connector<Box<dyn Transport>> for IdleTimeout {
+    type Out = Idle;
+
+    fn connect(&self, _: &ConnectionDetails, chained: Option<Box<dyn Transport>>) -> Result<Option<Idle>, ureq::Error> {
+        Ok(chained.map(|transport| Idle { transport, timeout: self.0 }))
+    }
+}
+
+/// A connection that waits at most `timeout` for every read, on top of the time limits of
+/// the request itself.
+#[derive(Debug)]
+struct Idle {
+    /// The connection, plain or encrypted.
+    transport: Box<dyn Transport>,
+    /// The longest wait for more data.
+    timeout: Duration,
+}
+
+impl Transport for Idle {
+    fn buffers(&mut self) -> &mut dyn Buffers {
+        self.transport.buffers()
+    }
+
+    fn transmit_output(&mut self, amount: usize, timeout: NextTimeout) -> Result<(), ureq::Error> {
+        self.transport.transmit_output(amount, timeout)
+    }
+
+    fn await_input(&mut self, timeout: NextTimeout) -> Result<bool, ureq::Error> {
+        let idle = NextTimeout { after: self.timeout.into(), reason: timeout.reason };
+        self.transport.await_input(if idle.after < timeout.after { idle } else { timeout })
+    }
+
+    fn is_open(&mut self) -> bool {
+        self.transport.is_open()
+    }
+
+    fn is_tls(&self) -> bool {
+        self.transport.is_tls()
+    }
+}
+
+/// Requests `url` and returns a reader of the response body, which is streamed as it is read.
+///
+/// # Errors
+///
+/// Returns an `HttpError` if the server answers with an error status, and an `OpenError` if
+/// the request cannot be made, such as when the host cannot be reached or does not answer in
+/// time.
+pub fn get(agent: &Agent, url: &str) -> Result<Box<dyn Read>, CrabError> {
+    match agent.get(url).call() {
+        Ok(response) => Ok(Box::new(Body(response.into_body().into_reader()))),
+        Err(ureq::Error::StatusCode(status)) => Err(HttpError(url.to_string(), status)),
+        Err(err) => Err(OpenError(url.to_string(), io_error(err))),
+    }
+}
+
+/// The body of a response, read with timeouts reported like those of the request.
+struct Body(ureq::BodyReader<'static>);
+
+impl Read for Body {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.0.read(buf).map_err(|err| match err.get_ref().and_then(|inner| inner.downcast_ref()) {
+            Some(ureq::Error::Timeout(_)) => timed_out(),
+            _ => err,
+        })
+    }
+}
+
+/// Converts an error of the HTTP client into an IO error.
+fn io_error(err: ureq::Error) -> Error {
+    match err {
+        ureq::Error::Io(err) => err,
+        ureq::Error::Timeout(_) => timed_out(),
+        ureq::Error::TooManyRedirects => Error::other(format!("more than {} redirects", MAX_REDIRECTS)),
+        err => Error::other(err.to_string()),
+    }
+}
+
+/// Returns the error for a server that did not connect, answer or send data in time.
+fn timed_out() -> Error {
+    Error::new(ErrorKind::TimedOut, "Connection timed out")
+}
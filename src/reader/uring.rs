@@ -0,0 +1,397 @@
+use super::{is_fifo, is_output, InputSource, Reader, SourceWriter};
+use crate::errors::CrabError::{self, InputIsOutput, OpenError};
+use io_uring::{opcode, squeue, types, IoUring, Probe};
+use std::ffi::CString;
+use std::fs::File;
+use std::io::{Error, ErrorKind, Result};
+use std::mem::ManuallyDrop;
+use std::os::fd::{FromRawFd, RawFd};
+use std::os::unix::ffi::OsStrExt;
+
+/// The number of files opened and read ahead at once.
+const BATCH: usize = 32;
+/// The size of the registered buffer each file of a batch is read into.
+const SLOT_SIZE: usize = 64 * 1024;
+
+/// Tags stored in the low bits of `user_data`, identifying the operation that completed.
+const OPEN: u64 = 0;
+const READ: u64 = 1;
+const WRITE: u64 = 2;
+const CLOSE: u64 = 3;
+
+/// The progress of one file of a batch.
+enum Slot {
+    /// Not a file, a FIFO, or a file that has already been copied or closed.
+    Empty,
+    /// The file is being opened.
+    Opening,
+    /// The file is open and a read into its buffer is in flight.
+    Reading(RawFd),
+    /// A read completed with the given result, the number of bytes or a negated errno.
+    Read(RawFd, i32),
+    /// The file could not be opened, with the given errno.
+    Failed(i32),
+}
+
+/// An io_uring instance with one registered buffer per file of a batch, and the progress of
+/// the batch it is reading.
+pub struct Ring {
+    /// The submission and completion queues.
+    ring: IoUring,
+    /// The registered buffers, `SLOT_SIZE` bytes for each file of a batch.
+    buffers: Box<[u8]>,
+    /// The progress of every file of the batch.
+    slots: Vec<Slot>,
+    /// The offset of the next read of every file of the batch.
+    offsets: Vec<u64>,
+    /// The paths of the files of the batch, which must stay alive until the kernel is done
+    /// opening them.
+    paths: Vec<CString>,
+    /// The number of operations queued that have not completed yet.
+    in_flight: usize,
+    /// The result of the last write, once it has completed.
+    written: Option<i32>,
+}
+
+impl Ring {
+    /// Sets up a ring and registers its buffers.
+    ///
+    /// # Returns
+    ///
+    /// The ring, or `None` if the kernel does not support io_uring, refuses to set it up, or
+    /// lacks one of the operations the copy uses, in which case the regular read and write
+    /// syscalls are used instead.
+    pub fn new() -> Option<Self> {
+        let ring = IoUring::new((BATCH * 2) as u32).ok()?;
+        let mut probe = Probe::new();
+        ring.submitter().register_probe(&mut probe).ok()?;
+        let operations = [opcode::OpenAt::CODE, opcode::ReadFixed::CODE, opcode::WriteFixed::CODE, opcode::Close::CODE];
+        if !operations.iter().all(|code| probe.is_supported(*code)) {
+            return None;
+        }
+        let mut buffers = vec![0; BATCH * SLOT_SIZE].into_boxed_slice();
+        let iovecs: Vec<libc::iovec> = buffers
+            .chunks_exact_mut(SLOT_SIZE)
+            .map(|buffer| libc::iovec { iov_base: buffer.as_mut_ptr().cast(), iov_len: SLOT_SIZE })
+            .collect();
+        // SAFETY: the buffers live on the heap as long as the ring, which unregisters them on drop.
+        unsafe { ring.submitter().register_buffers(&iovecs) }.ok()?;
+        Some(Ring { ring, buffers, slots: vec![], offsets: vec![], paths: vec![], in_flight: 0, written: None })
+    }
+
+    /// Starts a new batch of `len` files, none of which is being opened yet.
+    fn start(&mut self, len: usize) {
+        self.slots = (0..len).map(|_| Slot::Empty).collect();
+        self.offsets = vec![0; len];
+        self.paths.clear();
+    }
+
+    /// Queues opening a file into a slot.
+    fn open(&mut self, slot: usize, path: CString) -> Result<()> {
+        let entry = opcode::OpenAt::new(types::Fd(libc::AT_FDCWD), path.as_ptr())
+            .flags(libc::O_RDONLY | libc::O_CLOEXEC)
+            .build()
+            .user_data(tag(slot, OPEN));
+        // Moving the string keeps its heap buffer, which the entry points to, in place.
+        self.paths.push(path);
+        self.slots[slot] = Slot::Opening;
+        self.push(entry)
+    }
+
+    /// Returns a pointer to the registered buffer of a slot.
+    fn buffer(&mut self, slot: usize) -> *mut u8 {
+        self.buffers[slot * SLOT_SIZE..].as_mut_ptr()
+    }
+
+    /// Queues an operation, submitting the queue first if it is full.
+    fn push(&mut self, entry: squeue::Entry) -> Result<()> {
+        // SAFETY: every buffer and path referenced by an entry outlives its completion.
+        while unsafe { self.ring.submission().push(&entry) }.is_err() {
+            self.ring.submit()?;
+        }
+        self.in_flight += 1;
+        Ok(())
+    }
+
+    /// Queues a read of the next part of a file into the buffer of its slot.
+    fn read(&mut self, slot: usize, fd: RawFd, offset: u64) -> Result<()> {
+        let entry = opcode::ReadFixed::new(types::Fd(fd), self.buffer(slot), SLOT_SIZE as u32, slot as u16)
+            .offset(offset)
+            .build()
+            .user_data(tag(slot, READ));
+        self.push(entry)
+    }
+
+    /// Queues closing a file. Its completion is ignored.
+    fn close(&mut self, slot: usize, fd: RawFd) -> Result<()> {
+        self.push(opcode::Close::new(types::Fd(fd)).build().user_data(tag(slot, CLOSE)))
+    }
+
+    /// Writes part of the buffer of a slot to standard output, waiting until it is written.
+    fn write(&mut self, slot: usize, len: usize) -> Result<()> {
+        let mut done = 0;
+        while done < len {
+            // An offset of -1 writes at the current position, which also works for pipes and terminals.
+            let entry = opcode::WriteFixed::new(types::Fd(1), self.buffer(slot).wrapping_add(done), (len - done) as u32, slot as u16)
+                .offset(u64::MAX)
+                .build()
+                .user_data(tag(slot, WRITE));
+            self.push(entry)?;
+            let written = loop {
+                if let Some(written) = self.written.take() {
+                    break written;
+                }
+                self.wait()?;
+            };
+            if written < 0 {
+                return Err(Error::from_raw_os_error(-written));
+            }
+            done += written as usize;
+        }
+        Ok(())
+    }
+
+    /// Submits the queued operations, waits for at least one to complete, and records the
+    /// results. A file that finishes opening immediately starts reading into its buffer.
+    fn wait(&mut self) -> Result<()> {
+        for (slot, operation, result) in self.complete()? {
+            match operation {
+                OPEN if result >= 0 => {
+                    self.offsets[slot] = 0;
+                    self.slots[slot] = Slot::Reading(result);
+                    self.read(slot, result, 0)?;
+                }
+                OPEN => self.slots[slot] = Slot::Failed(-result),
+                READ => {
+                    if let Slot::Reading(fd) = self.slots[slot] {
+                        self.slots[slot] = Slot::Read(fd, result);
+                    }
+                }
+                WRITE => self.written = Some(result),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Submits the queued operations, waits for at least one to complete, and returns the slot,
+    /// operation and result of every completed one.
+    fn complete(&mut self) -> Result<Vec<(usize, u64, i32)>> {
+        self.ring.submit_and_wait(1)?;
+        let completed: Vec<_> = self
+            .ring
+            .completion()
+            .map(|cqe| ((cqe.user_data() >> 2) as usize, cqe.user_data() & 3, cqe.result()))
+            .collect();
+        self.in_flight -= completed.len();
+        Ok(completed)
+    }
+
+    /// Waits for every operation still in flight and closes the files of the batch, without
+    /// queueing anything else. This is done before the copy stops at an error, so the kernel
+    /// is done with the paths of the batch before they are dropped, and no file stays open.
+    /// Errors here are ignored in favor of the one that stopped the copy.
+    fn abandon(&mut self) {
+        while self.in_flight > 0 {
+            let completed = match self.complete() {
+                Ok(completed) => completed,
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(_) => {
+                    // The operations cannot be waited for, so keep the paths they refer to alive.
+                    std::mem::forget(std::mem::take(&mut self.paths));
+                    break;
+                }
+            };
+            for (slot, operation, result) in completed {
+                match operation {
+                    // SAFETY: the descriptor was just opened for this batch, and nothing else uses it.
+                    OPEN if result >= 0 => unsafe { drop(File::from_raw_fd(result)) },
+                    READ => {
+                        if let Slot::Reading(fd) = self.slots[slot] {
+                            self.slots[slot] = Slot::Read(fd, result);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        for slot in std::mem::take(&mut self.slots) {
+            if let Slot::Reading(fd) | Slot::Read(fd, _) = slot {
+                // SAFETY: the descriptor belongs to this batch, and no operation uses it anymore.
+                unsafe { drop(File::from_raw_fd(fd)) };
+            }
+        }
+    }
+}
+
+/// Returns whether an operation failed with `errno` because the ring does not support it for
+/// this file, such as a file system without support for fixed buffers.
+fn unsupported(errno: i32) -> bool {
+    errno == libc::EINVAL || errno == libc::EOPNOTSUPP
+}
+
+/// Packs a slot index and an operation tag into the `user_data` of an entry.
+fn tag(slot: usize, operation: u64) -> u64 {
+    ((slot as u64) << 2) | operation
+}
+
+impl Reader {
+    /// Copy the data of every input source to a writer, opening and reading files in batches
+    /// through io_uring.
+    ///
+    /// The next `BATCH` sources are taken at once, every file among them is opened, and each one
+    /// starts reading into its own registered buffer as soon as it is open. The files are then
+    /// copied in order while the kernel keeps reading ahead the others. Standard input and errors
+    /// from the sources iterator are handled as in `copy_to`. Files are never mapped into memory
+    /// on this path, and it is not used when sources must only be taken once their turn has come.
+    ///
+    /// # Arguments
+    ///
+    /// * `ring` - The ring used to open, read and close the files.
+    /// * `writer` - The writer that receives the data.
+    /// * `direct` - Write straight to standard output from the registered buffers, through the
+    ///   ring, instead of going through `writer`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing to or flushing the output fails.
+    pub(super) fn copy_batched<W: SourceWriter>(&mut self, ring: &mut Ring, writer: &mut W, direct: bool) -> Result<()> {
+        let mut buf = vec![];
+        loop {
+            let batch: Vec<_> = self.sources.by_ref().take(BATCH).collect();
+            if batch.is_empty() {
+                return Ok(());
+            }
+            if let Err(err) = self.copy_batch(ring, batch, &mut buf, writer, direct) {
+                // Nothing in flight may outlive the batch it belongs to.
+                ring.abandon();
+                return Err(err);
+            }
+        }
+    }
+
+    /// Copy one batch of sources, as described in `copy_batched`.
+    fn copy_batch<W: SourceWriter>(
+        &mut self,
+        ring: &mut Ring,
+        batch: Vec<std::result::Result<InputSource, CrabError>>,
+        buf: &mut Vec<u8>,
+        writer: &mut W,
+        direct: bool,
+    ) -> Result<()> {
+        // Open every file of the batch at once.
+        ring.start(batch.len());
+        for (slot, source) in batch.iter().enumerate() {
+            let Ok(InputSource::File(path)) = source else {
+                continue;
+            };
+            // Opening or reading a FIFO can wait for its writer indefinitely, which would hold
+            // up the whole batch, so FIFOs are read the regular way.
+            if is_fifo(path) {
+                continue;
+            }
+            match CString::new(path.as_os_str().as_bytes()) {
+                Ok(path) => ring.open(slot, path)?,
+                Err(_) => ring.slots[slot] = Slot::Failed(libc::EINVAL),
+            }
+        }
+        ring.ring.submit()?;
+
+        // Copy the sources in order.
+        for (slot, source) in batch.into_iter().enumerate() {
+            let source = match source {
+                Ok(source @ InputSource::File(_)) if !matches!(ring.slots[slot], Slot::Empty) => source,
+                Ok(source) => {
+                    // Standard input, FIFOs and sources other than files are read the regular way.
+                    self.copy_regular(source, buf, writer)?;
+                    continue;
+                }
+                Err(err) => {
+                    self.fail(writer, err)?;
+                    continue;
+                }
+            };
+
+            loop {
+                // Wait until the next part of this file is in its buffer.
+                while matches!(ring.slots[slot], Slot::Opening | Slot::Reading(_)) {
+                    ring.wait()?;
+                }
+                match std::mem::replace(&mut ring.slots[slot], Slot::Empty) {
+                    // Files the ring cannot open or read are read the regular way.
+                    Slot::Failed(errno) if unsupported(errno) => {
+                        self.copy_regular(source.clone(), buf, writer)?;
+                        break;
+                    }
+                    Slot::Read(fd, read) if ring.offsets[slot] == 0 && unsupported(-read) => {
+                        ring.close(slot, fd)?;
+                        self.copy_regular(source.clone(), buf, writer)?;
+                        break;
+                    }
+                    Slot::Failed(errno) => {
+                        self.fail(writer, OpenError(source.name().into_owned(), Error::from_raw_os_error(errno)))?;
+                        break;
+                    }
+                    Slot::Read(fd, read) => {
+                        // Refuse files that are the same as standard output, before copying anything.
+                        if ring.offsets[slot] == 0 && !self.outputs.is_empty() {
+                            // SAFETY: the descriptor is open, and is only borrowed here.
+                            let file = ManuallyDrop::new(unsafe { File::from_raw_fd(fd) });
+                            if is_output(file.try_clone(), &self.outputs) {
+                                ring.close(slot, fd)?;
+                                self.fail(writer, InputIsOutput(source.name().into_owned()))?;
+                                break;
+                            }
+                        }
+                        if ring.offsets[slot] == 0 {
+                            writer.start_source(&source)?;
+                        }
+                        if read <= 0 {
+                            ring.close(slot, fd)?;
+                            if read < 0 {
+                                self.fail(writer, CrabError::read(source.clone(), Error::from_raw_os_error(-read)))?;
+                            }
+                            writer.end_source()?;
+                            break;
+                        }
+
+                        // Put the slot back first, so the file is closed if the copy stops here.
+                        ring.slots[slot] = Slot::Read(fd, read);
+                        let len = read as usize;
+                        if direct {
+                            ring.write(slot, len)?;
+                        } else {
+                            // SAFETY: the kernel is done with this buffer until the next read is queued.
+                            let data = unsafe { std::slice::from_raw_parts(ring.buffer(slot), len) };
+                            writer.write_all(data)?;
+                        }
+                        ring.offsets[slot] += len as u64;
+                        ring.slots[slot] = Slot::Reading(fd);
+                        ring.read(slot, fd, ring.offsets[slot])?;
+                    }
+                    _ => unreachable!(),
+                }
+            }
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Copies a source the regular way, as `copy_to` does, for the sources the ring does not
+    /// read. `buf` is only allocated once it is needed.
+    fn copy_regular<W: SourceWriter>(&mut self, source: InputSource, buf: &mut Vec<u8>, writer: &mut W) -> Result<()> {
+        if buf.is_empty() {
+            *buf = vec![0; super::BUFFER_SIZE];
+        }
+        match self.open(source) {
+            Ok(Some((source, mut input))) => {
+                writer.start_source(&source)?;
+                self.copy_input(&source, &mut input, buf, writer)?;
+                writer.end_source()?;
+            }
+            Ok(None) => {}
+            Err(err) => self.fail(writer, err)?,
+        }
+        writer.flush()
+    }
+}
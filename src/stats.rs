@@ -0,0 +1,205 @@
+use crate::format::{FormatOptions, FormatState};
+use std::io::Write;
+
+/// What `--stats` counts in an input, or in all of them.
+#[derive(Clone, Copy, Default)]
+pub struct Counts {
+    /// The number of newlines, like `wc -l`.
+    lines: u64,
+    /// The number of runs of bytes other than ASCII whitespace.
+    words: u64,
+    /// The number of bytes.
+    bytes: u64,
+    /// The length in bytes of the longest line, without its newline.
+    longest: u64,
+    /// The number of bytes `-v` shows with `^` or `M-` notation.
+    non_printing: u64,
+    /// The number of TAB characters.
+    tabs: u64,
+    /// The number of carriage returns.
+    crs: u64,
+    /// The number of empty lines `-s` removed.
+    squeezed: u64,
+    /// The number of byte sequences that are not valid UTF-8.
+    invalid: u64,
+}
+
+impl Counts {
+    /// Adds the counts of another input to these, keeping the longest line of both.
+    fn add(&mut self, other: &Counts) {
+        self.lines += other.lines;
+        self.words += other.words;
+        self.bytes += other.bytes;
+        self.longest = self.longest.max(other.longest);
+        self.non_printing += other.non_printing;
+        self.tabs += other.tabs;
+        self.crs += other.crs;
+        self.squeezed += other.squeezed;
+        self.invalid += other.invalid;
+    }
+
+    /// Returns the counts in the order of the columns of the table.
+    fn columns(&self) -> [u64; 9] {
+        [
+            self.lines,
+            self.words,
+            self.bytes,
+            self.longest,
+            self.non_printing,
+            self.tabs,
+            self.crs,
+            self.squeezed,
+            self.invalid,
+        ]
+    }
+}
+
+/// The headers of the columns of the table, in the order of `Counts::columns`.
+const HEADERS: [&str; 10] = ["lines", "words", "bytes", "longest", "nonprint", "tabs", "crs", "squeezed", "invalid", "source"];
+
+/// The counts of every input, and the state carried from one write to the next to compute
+/// them.
+///
+/// Lines, words and the UTF-8 validity are counted within each input, like `wc` does for each
+/// file. Empty lines are squeezed across inputs, since `-s` carries on from one input to the
+/// next like in GNU cat.
+pub struct StatsState {
+    /// The formatting state of `-s`, which tells the empty lines it removes.
+    squeeze: FormatState,
+    /// The name and the counts of every input done so far.
+    sources: Vec<(String, Counts)>,
+    /// The name and the counts of the input being read, if any.
+    current: Option<(String, Counts)>,
+    /// Whether the last byte was part of a word.
+    in_word: bool,
+    /// The length of the current line so far.
+    line_length: u64,
+    /// The start of a UTF-8 sequence that continues in the next write.
+    partial: Vec<u8>,
+}
+
+impl StatsState {
+    /// Creates the state before any input, counting squeezed empty lines if `squeeze` is set.
+    pub fn new(squeeze: bool) -> Self {
+        StatsState {
+            squeeze: FormatState::new(FormatOptions { squeeze_blank: squeeze, ..FormatOptions::default() }),
+            sources: vec![],
+            current: None,
+            in_word: false,
+            line_length: 0,
+            partial: vec![],
+        }
+    }
+
+    /// Starts counting a new input.
+    pub fn start_source(&mut self, name: &str) {
+        self.current = Some((name.to_string(), Counts::default()));
+        self.in_word = false;
+        self.line_length = 0;
+    }
+
+    /// Adds the bytes of `buf` to the counts of the current input. The end of an encoding,
+    /// written once all inputs are done, goes with the last one.
+    pub fn update(&mut self, buf: &[u8]) {
+        let Some((_, counts)) = self.current.as_mut().or(self.sources.last_mut()) else {
+            return;
+        };
+        counts.bytes += buf.len() as u64;
+        let squeezed = self.squeeze.squeezed();
+        self.squeeze.skip(buf);
+        counts.squeezed += (self.squeeze.squeezed() - squeezed) as u64;
+        for &byte in buf {
+            let space = byte.is_ascii_whitespace() || byte == 0x0b;
+            if !space && !self.in_word {
+                counts.words += 1;
+            }
+            self.in_word = !space;
+            match byte {
+                b'\n' => {
+                    counts.lines += 1;
+                    counts.longest = counts.longest.max(self.line_length);
+                    self.line_length = 0;
+                    continue;
+                }
+                b'\t' => counts.tabs += 1,
+                b'\r' => {
+                    counts.crs += 1;
+                    counts.non_printing += 1;
+                }
+                0..=31 | 127..=255 => counts.non_printing += 1,
+                _ => {}
+            }
+            self.line_length += 1;
+        }
+        counts.invalid += invalid_utf8(&mut self.partial, buf);
+    }
+
+    /// Finishes counting the current input. A UTF-8 sequence it ends in the middle of is invalid.
+    pub fn end_source(&mut self) {
+        if let Some((name, mut counts)) = self.current.take() {
+            counts.longest = counts.longest.max(self.line_length);
+            if !self.partial.is_empty() {
+                self.partial.clear();
+                counts.invalid += 1;
+            }
+            self.sources.push((name, counts));
+        }
+    }
+
+    /// Writes the table of the counts of every input, followed by their totals if there is
+    /// more than one, like `wc`.
+    pub fn write_table<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        let mut rows: Vec<(&str, Counts)> = self.sources.iter().map(|(name, counts)| (name.as_str(), *counts)).collect();
+        if rows.len() > 1 {
+            let mut total = Counts::default();
+            rows.iter().for_each(|(_, counts)| total.add(counts));
+            rows.push(("total", total));
+        }
+        // Every column is as wide as its widest number or its header.
+        let mut widths = HEADERS.map(str::len);
+        for (_, counts) in &rows {
+            for (width, count) in widths.iter_mut().zip(counts.columns()) {
+                *width = (*width).max(count.to_string().len());
+            }
+        }
+
+        let header: Vec<String> = HEADERS[..9].iter().zip(widths).map(|(header, width)| format!("{:>1$}", header, width)).collect();
+        writeln!(writer, "{}  {}", header.join("  "), HEADERS[9])?;
+        for (name, counts) in rows {
+            let row: Vec<String> = counts.columns().iter().zip(widths).map(|(count, width)| format!("{:>1$}", count, width)).collect();
+            writeln!(writer, "{}  {}", row.join("  "), name)?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the number of invalid UTF-8 sequences in `partial` followed by `buf`, and leaves in
+/// `partial` the start of a sequence that may be completed by the next buffer.
+fn invalid_utf8(partial: &mut Vec<u8>, buf: &[u8]) -> u64 {
+    let joined;
+    let mut rest = if partial.is_empty() {
+        buf
+    } else {
+        joined = [partial.as_slice(), buf].concat();
+        &joined
+    };
+    let mut invalid = 0;
+    loop {
+        match std::str::from_utf8(rest) {
+            Ok(_) => {
+                partial.clear();
+                return invalid;
+            }
+            Err(err) => match err.error_len() {
+                Some(len) => {
+                    invalid += 1;
+                    rest = &rest[err.valid_up_to() + len..];
+                }
+                None => {
+                    *partial = rest[err.valid_up_to()..].to_vec();
+                    return invalid;
+                }
+            },
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use proptest::prelude::*;
+use proptest::sample::subsequence;
+use proptest::test_runner::FileFailurePersistence;
+use std::fs::File;
+use std::os::unix::fs::{symlink, PermissionsExt};
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+
+/// The flags crab shares with GNU cat.
+const FLAGS: [&str; 10] = ["-n", "-b", "-s", "-v", "-E", "-T", "-A", "-e", "-t", "-bn"];
+
+/// Generates byte streams made of the pieces the formatting cares about: newlines, CRLF line
+/// endings, tabs, control bytes and high bytes, with some printable text in between.
+fn bytes() -> impl Strategy<Value = Vec<u8>> {
+    let piece = prop_oneof![
+        6 => Just(b"\n".to_vec()),
+        3 => Just(b"\r\n".to_vec()),
+        2 => Just(b"\r".to_vec()),
+        2 => Just(b"\t".to_vec()),
+        1 => Just(vec![0]),
+        2 => (0u8..32).prop_map(|byte| vec![byte]),
+        2 => (127u8..=255).prop_map(|byte| vec![byte]),
+        4 => "[ -~]{1,8}".prop_map(String::into_bytes),
+    ];
+    prop::collection::vec(piece, 0..64).prop_map(|pieces| pieces.concat())
+}
+
+/// An operand of the command line.
+#[derive(Debug, Clone)]
+enum Operand {
+    /// Standard input.
+    Stdin,
+    /// A file with the given contents.
+    File(Vec<u8>),
+    /// A file that does not exist.
+    Missing,
+    /// A directory.
+    Directory,
+    /// A file without read permission, which can still be read by root.
+    Unreadable(Vec<u8>),
+}
+
+fn operand() -> impl Strategy<Value = Operand> {
+    prop_oneof![
+        2 => Just(Operand::Stdin),
+        6 => bytes().prop_map(Operand::File),
+        1 => Just(Operand::Missing),
+        1 => Just(Operand::Directory),
+        1 => bytes().prop_map(Operand::Unreadable),
+    ]
+}
+
+/// Runs `program` with the given flags and operands in `dir`, feeding `stdin` to it.
+fn run(program: &Path, dir: &Path, flags: &[&str], operands: &[String], stdin: &Path) -> Output {
+    Command::new(program)
+        .current_dir(dir)
+        .args(flags)
+        .args(operands)
+        .stdin(Stdio::from(File::open(stdin).unwrap()))
+        .output()
+        .unwrap()
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig {
+        cases: 256,
+        failure_persistence: Some(Box::new(FileFailurePersistence::SourceParallel("proptest-regressions"))),
+        ..ProptestConfig::default()
+    })]
+
+    /// crab and GNU cat produce the same stdout, stderr and exit code for any input,
+    /// flags and layout of operands.
+    #[test]
+    fn same_as_cat(
+        flags in subsequence(FLAGS.to_vec(), 0..=3),
+        operands in prop::collection::vec(operand(), 0..4),
+        stdin in bytes(),
+    ) {
+        let dir = tempfile::tempdir().unwrap();
+        let stdin_path = dir.path().join("stdin");
+        std::fs::write(&stdin_path, &stdin).unwrap();
+        let names: Vec<String> = operands
+            .iter()
+            .enumerate()
+            .map(|(i, operand)| {
+                let name = format!("{}.txt", i);
+                let path = dir.path().join(&name);
+                match operand {
+                    Operand::Stdin => return String::from("-"),
+                    Operand::File(contents) => std::fs::write(path, contents).unwrap(),
+                    Operand::Missing => {}
+                    Operand::Directory => std::fs::create_dir(path).unwrap(),
+                    Operand::Unreadable(contents) => {
+                        std::fs::write(&path, contents).unwrap();
+                        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o200)).unwrap();
+                    }
+                }
+                name
+            })
+            .collect();
+
+        // Errors are prefixed with the name the program was invoked as, so crab is run as cat.
+        let bin = tempfile::tempdir().unwrap();
+        symlink(assert_cmd::cargo::cargo_bin("crab"), bin.path().join("cat")).unwrap();
+        let crab = run(&bin.path().join("cat"), dir.path(), &flags, &names, &stdin_path);
+        let cat = run(Path::new("cat"), dir.path(), &flags, &names, &stdin_path);
+        prop_assert_eq!(crab.status.code(), cat.status.code());
+        prop_assert_eq!(String::from_utf8_lossy(&crab.stderr), String::from_utf8_lossy(&cat.stderr));
+        prop_assert_eq!(crab.stdout, cat.stdout);
+    }
+}
@@ -1,11 +1,14 @@
-use lazy_static::lazy_static;
+use rand::rngs::StdRng;
 use rand::seq::SliceRandom;
-use std::ffi::OsStr;
+use rand::SeedableRng;
 use std::fs::File;
 use std::io::{BufWriter, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::Command;
-use std::sync::Mutex;
+use tempfile::TempDir;
+
+/// The seed of the random fixture, so every run tests the same input.
+const SEED: u64 = 0xCAB;
 
 pub enum TestFile {
     Random,
@@ -15,28 +18,27 @@ pub enum TestFile {
 }
 
 impl TestFile {
-    const _RANDOM: &'static str = "test_inputs/random.txt";
-    const _ALL_THE_BYTES: &'static str = "test_inputs/all_the_bytes.txt";
-    const _NO_PERMISSIONS: &'static str = "test_inputs/no_permission.txt";
-
-    fn get_path(&self) -> PathBuf {
+    /// Returns the name of the fixture inside its directory, or `-` for standard input.
+    pub fn name(&self) -> &'static str {
         match self {
-            TestFile::Random => PathBuf::from(TestFile::_RANDOM),
-            TestFile::AllTheBytes => PathBuf::from(TestFile::_ALL_THE_BYTES),
-            TestFile::NoPermissions => PathBuf::from(TestFile::_NO_PERMISSIONS),
-            TestFile::Pipe => panic!("This will never happen"),
+            TestFile::Random => "random.txt",
+            TestFile::AllTheBytes => "all_the_bytes.txt",
+            TestFile::NoPermissions => "no_permission.txt",
+            TestFile::Pipe => "-",
         }
     }
-    fn create(&self) {
-        let file = File::create(self.get_path()).unwrap();
+
+    fn create(&self, path: &Path) {
+        let file = File::create(path).unwrap();
         let mut writer = BufWriter::new(file);
         match self {
             TestFile::Random => {
                 let mut chars: Vec<u8> = (33u8..=0x7E).collect::<Vec<u8>>();
                 chars.append(&mut vec![9, 10]);
-                let mut rng = rand::thread_rng();
+                let mut rng = StdRng::seed_from_u64(SEED);
+                #[allow(clippy::inconsistent_digit_grouping)]
                 for _ in 0..=10_00_000 {
-                    write!(writer, "{}", *chars.choose(&mut rng).unwrap() as char).unwrap();
+                    writer.write_all(&[*chars.choose(&mut rng).unwrap()]).unwrap();
                 }
             }
             TestFile::AllTheBytes => {
@@ -47,41 +49,66 @@ impl TestFile {
                 }
             }
             TestFile::NoPermissions => {
-                let path = self.get_path();
-                Command::new("chmod")
-                    .args(["000", path.to_str().unwrap()])
-                    .spawn()
-                    .expect("Fail creating child")
-                    .wait()
-                    .expect("Fail changing permission");
+                #[cfg(unix)]
+                {
+                    use std::os::unix::fs::PermissionsExt;
+                    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o000)).unwrap();
+                }
             }
-            _ => {}
+            TestFile::Pipe => {}
         }
     }
-    pub fn get(&self) -> &'static str {
-        lazy_static! {
-            static ref MUT: Mutex<()> = Mutex::new(());
-        };
-        let _guard = MUT.lock().unwrap();
-        let _ = std::fs::create_dir(PathBuf::from("test_inputs"));
-        if let TestFile::Pipe = self {
-            return "-";
-        }
-        let path = self.get_path();
-        if !path.exists() {
-            self.create()
-        }
-        match self {
-            TestFile::Random => TestFile::_RANDOM,
-            TestFile::AllTheBytes => TestFile::_ALL_THE_BYTES,
-            TestFile::NoPermissions => TestFile::_NO_PERMISSIONS,
-            _ => "-",
+}
+
+/// A temporary directory holding the fixtures of a single test, removed when dropped.
+///
+/// Commands created through `crab` and `cat` run inside the directory, so fixtures are passed
+/// by their plain names and show up that way in error messages.
+pub struct Fixtures {
+    dir: TempDir,
+}
+
+impl Fixtures {
+    pub fn new() -> Self {
+        Fixtures { dir: tempfile::tempdir().unwrap() }
+    }
+
+    /// Returns the path of the directory.
+    pub fn dir(&self) -> &Path {
+        self.dir.path()
+    }
+
+    /// Returns the path of a file in the directory.
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.dir().join(name)
+    }
+
+    /// Returns the name of a fixture, creating it on first use.
+    pub fn get(&self, file: &TestFile) -> &'static str {
+        let path = self.path(file.name());
+        if !matches!(file, TestFile::Pipe) && !path.exists() {
+            file.create(&path);
         }
+        file.name()
+    }
+
+    /// Returns whether the permissions of `NoPermissions` keep it from being read, which is
+    /// not the case when the tests run as root.
+    pub fn permissions_enforced(&self) -> bool {
+        File::open(self.path(self.get(&TestFile::NoPermissions))).is_err()
+    }
+
+    /// Returns a command running crab inside the directory.
+    pub fn crab(&self) -> assert_cmd::Command {
+        let mut crab = assert_cmd::Command::cargo_bin("crab").unwrap();
+        crab.current_dir(self.dir());
+        crab
     }
-}
 
-impl AsRef<OsStr> for TestFile {
-    fn as_ref(&self) -> &OsStr {
-        OsStr::new(self.get())
+    /// Returns a command running the system cat inside the directory.
+    pub fn cat(&self) -> Command {
+        let mut cat = Command::new("cat");
+        cat.current_dir(self.dir());
+        cat
     }
 }
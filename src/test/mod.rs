@@ -1,2 +1,4 @@
 pub mod tests;
+#[cfg(unix)]
+mod differential;
 mod files;
\ No newline at end of file
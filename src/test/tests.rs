@@ -1,31 +1,35 @@
-use crate::test::files::TestFile;
+#[cfg(unix)]
+use crate::mmap::{MappedFile, MMAP_THRESHOLD, WINDOW_SIZE};
+use crate::format::FormatOptions;
+use crate::pipeline::{Pipeline, CHUNK_SIZE};
+use crate::test::files::{Fixtures, TestFile};
+use assert_cmd::prelude::{CommandCargoExt, OutputAssertExt};
 use crate::test::files::TestFile::{AllTheBytes, NoPermissions, Pipe, Random};
 use predicates::prelude::predicate;
 use std::fs::File;
-use std::path::PathBuf;
+use std::io::Write;
 use std::process::{Command, Output, Stdio};
-
-fn crab_cat() -> (assert_cmd::Command, Command) {
-    (
-        assert_cmd::Command::cargo_bin("crab").unwrap(),
-        Command::new("cat"),
-    )
-}
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 
 fn compare_with_cat(
+    fixtures: &Fixtures,
     stdin: Option<TestFile>,
     flags: Vec<&str>,
     files: Vec<TestFile>,
 ) -> (assert_cmd::Command, Output) {
-    let (mut crab, mut cat) = crab_cat();
+    let (mut crab, mut cat) = (fixtures.crab(), fixtures.cat());
+    let files: Vec<&str> = files.iter().map(|file| fixtures.get(file)).collect();
     crab.args(&files);
     crab.args(&flags);
     cat.args(&files);
     cat.args(&flags);
     let output = if let Some(stdin) = stdin {
-        crab.pipe_stdin(PathBuf::from(stdin.get()).as_path())
+        let path = fixtures.path(fixtures.get(&stdin));
+        crab.pipe_stdin(&path)
             .unwrap();
-        cat.stdin(Stdio::from(File::open(stdin.get()).unwrap()))
+        cat.stdin(Stdio::from(File::open(&path).unwrap()))
     } else {
         &mut cat
     }
@@ -35,11 +39,20 @@ fn compare_with_cat(
 }
 
 fn std_eq(stdin: Option<TestFile>, flags: Vec<&str>, files: Vec<TestFile>) {
-    let (mut crab, cat_out) = compare_with_cat(stdin, flags, files);
+    let fixtures = Fixtures::new();
+    let (mut crab, cat_out) = compare_with_cat(&fixtures, stdin, flags, files);
     crab.assert()
         .stdout(predicate::eq(cat_out.stdout.as_slice()));
 }
 
+fn std_eq_threads(stdin: Option<TestFile>, flags: Vec<&str>, files: Vec<TestFile>) {
+    let fixtures = Fixtures::new();
+    let (mut crab, cat_out) = compare_with_cat(&fixtures, stdin, flags, files);
+    crab.args(["--threads", "3"])
+        .assert()
+        .stdout(predicate::eq(cat_out.stdout.as_slice()));
+}
+
 #[test]
 fn random() {
     std_eq(None, vec![], vec![Random]);
@@ -72,11 +85,28 @@ fn random_plus_random() {
 fn random_plus_random_stdin() {
     std_eq(Some(Random), vec![], vec![Random]);
 }
+#[test]
+fn random_plus_random_numbered_threads() {
+    std_eq_threads(None, vec!["-n"], vec![Random, Random]);
+}
+#[test]
+fn stdin_numbered_non_blank_squeeze_threads() {
+    std_eq_threads(Some(Random), vec!["-bs"], vec![Pipe]);
+}
+#[test]
+fn stdin_in_the_middle_threads() {
+    std_eq_threads(Some(Random), vec!["-A"], vec![AllTheBytes, Pipe, AllTheBytes]);
+}
 #[cfg(unix)]
 #[test]
 fn fail_on_read() {
-    let (mut crab, _) = crab_cat();
-    crab.arg(NoPermissions)
+    let fixtures = Fixtures::new();
+    if !fixtures.permissions_enforced() {
+        eprintln!("skipping fail_on_read: file permissions are not enforced for this user");
+        return;
+    }
+    fixtures.crab()
+        .arg(fixtures.get(&NoPermissions))
         .assert()
         .failure()
         .stderr(predicate::str::contains(" Permission denied"));
@@ -100,15 +130,872 @@ fn stdin_in_the_middle() {
 #[cfg(unix)]
 #[test]
 fn fail_but_print() {
+    let fixtures = Fixtures::new();
     let (mut crab, cat_res) = compare_with_cat(
+        &fixtures,
         Some(AllTheBytes),
         vec!["-b"],
         vec![NoPermissions, Pipe, Random],
     );
-    crab.assert()
+    let assert = crab.assert()
+        .stdout(predicate::eq(cat_res.stdout.as_slice()));
+    // When running as root the unreadable file can be read after all, just as cat does.
+    if fixtures.permissions_enforced() {
+        assert.failure()
+            .stderr(predicate::str::contains(
+                " no_permission.txt: Permission denied",
+            ));
+    } else {
+        assert.success();
+    }
+}
+#[test]
+fn crlf_line_endings() {
+    let fixtures = Fixtures::new();
+    // The carriage return of a CRLF line ending is shown as ^M before the $ of -E, like GNU cat.
+    fixtures.crab()
+        .args(["-E"])
+        .write_stdin("a\r\nb\r\n\r")
+        .assert()
+        .success()
+        .stdout("a^M$\nb^M$\n\r");
+    // A line with only a carriage return is not empty, so it is numbered and never squeezed.
+    fixtures.crab()
+        .args(["-sn"])
+        .write_stdin("\r\n\r\n\n\n")
+        .assert()
+        .success()
+        .stdout("     1\t\r\n     2\t\r\n     3\t\n");
+}
+#[test]
+fn repeated_flags() {
+    // Flags given more than once are accepted, like GNU cat does.
+    Fixtures::new().crab()
+        .args(["-b", "-bn", "--threads", "2", "--threads", "1"])
+        .write_stdin("x\n\ny\n")
+        .assert()
+        .success()
+        .stdout("     1\tx\n\n     2\ty\n");
+}
+#[cfg(unix)]
+#[test]
+fn input_is_output() {
+    let fixtures = Fixtures::new();
+    let path = fixtures.path("input_is_output.txt");
+    std::fs::write(&path, "crab\n").unwrap();
+    let output = std::fs::OpenOptions::new().append(true).open(&path).unwrap();
+    Command::cargo_bin("crab")
+        .unwrap()
+        .current_dir(fixtures.dir())
+        .args(["input_is_output.txt", fixtures.get(&Random)])
+        .stdout(output)
+        .assert()
+        .failure()
+        .code(1)
+        .stderr(predicate::str::contains("input_is_output.txt: input file is output file"));
+    let contents = std::fs::read(&path).unwrap();
+    assert!(contents.starts_with(b"crab\n"));
+    assert_eq!(contents.len() - 5, std::fs::read(fixtures.path(Random.name())).unwrap().len());
+}
+#[test]
+fn files_from_null_delimited() {
+    let fixtures = Fixtures::new();
+    let list = format!("{}\0{}\0", fixtures.get(&Random), fixtures.get(&AllTheBytes));
+    std::fs::write(fixtures.path("files_from_null.txt"), list).unwrap();
+    let (_, cat_out) = compare_with_cat(&fixtures, None, vec!["-n"], vec![Random, AllTheBytes]);
+    fixtures.crab()
+        .args(["-n", "-0", "--files-from", "files_from_null.txt"])
+        .assert()
+        .success()
+        .stdout(predicate::eq(cat_out.stdout.as_slice()));
+}
+#[cfg(unix)]
+#[test]
+fn files_from_non_utf8_names() {
+    use std::os::unix::ffi::OsStrExt;
+    let fixtures = Fixtures::new();
+    let name = std::ffi::OsStr::from_bytes(b"caf\xe9.txt");
+    std::fs::write(fixtures.dir().join(name), "latin-1 name\n").unwrap();
+    fixtures.crab()
+        .args(["-0", "--files-from", "-"])
+        .write_stdin(&b"caf\xe9.txt\0"[..])
+        .assert()
+        .success()
+        .stdout("latin-1 name\n");
+}
+#[test]
+fn files_from_stdin() {
+    let fixtures = Fixtures::new();
+    let (_, cat_out) = compare_with_cat(&fixtures, None, vec!["-b"], vec![AllTheBytes, Random]);
+    fixtures.crab()
+        .args(["-b", "--files-from", "-"])
+        .write_stdin(format!("{}\n\n{}\n", AllTheBytes.name(), Random.name()))
+        .assert()
+        .success()
+        .stdout(predicate::eq(cat_out.stdout.as_slice()));
+}
+#[cfg(unix)]
+#[test]
+fn errors_interleaved_with_output() {
+    let fixtures = Fixtures::new();
+    let path = fixtures.path("interleaved.txt");
+    let output = File::create(&path).unwrap();
+    Command::cargo_bin("crab")
+        .unwrap()
+        .current_dir(fixtures.dir())
+        .args(["-n", fixtures.get(&AllTheBytes), "missing.txt", AllTheBytes.name()])
+        .stdout(output.try_clone().unwrap())
+        .stderr(output)
+        .assert()
+        .failure();
+    let (_, first) = compare_with_cat(&fixtures, None, vec!["-n"], vec![AllTheBytes]);
+    let (_, both) = compare_with_cat(&fixtures, None, vec!["-n"], vec![AllTheBytes, AllTheBytes]);
+    let combined = std::fs::read(&path).unwrap();
+
+    // The first file is written before the error, and the second one after it.
+    assert!(combined.starts_with(&first.stdout));
+    let rest = &combined[first.stdout.len()..];
+    let end_of_error = rest.iter().position(|&b| b == b'\n').unwrap() + 1;
+    assert!(String::from_utf8_lossy(&rest[..end_of_error])
+        .contains("missing.txt: No such file or directory"));
+    assert_eq!(&rest[end_of_error..], &both.stdout[first.stdout.len()..]);
+}
+#[cfg(unix)]
+#[test]
+fn mapped_file_truncated_while_reading() {
+    let fixtures = Fixtures::new();
+    let path = fixtures.path("mapped_truncated.txt");
+    let contents: Vec<u8> = (0..4 * MMAP_THRESHOLD).map(|i| b"crab\n"[i as usize % 5]).collect();
+    std::fs::write(&path, &contents).unwrap();
+    let mapped = MappedFile::map(File::open(&path).unwrap()).ok().unwrap();
+
+    // Shrink the file after it has been mapped, so the pages past the new end fault.
+    let new_len = MMAP_THRESHOLD + 3;
+    std::fs::OpenOptions::new().write(true).open(&path).unwrap().set_len(new_len).unwrap();
+    let mut output = vec![];
+    assert!(mapped.write_to(&mut output).unwrap());
+    assert_eq!(output, &contents[..new_len as usize]);
+}
+#[cfg(unix)]
+#[test]
+fn mapped_file_truncated_during_copy() {
+    /// A writer that shrinks the file while it writes the first window.
+    struct Truncating {
+        file: File,
+        len: u64,
+        output: Vec<u8>,
+    }
+    impl Write for Truncating {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            if self.output.is_empty() {
+                self.file.set_len(self.len)?;
+            }
+            self.output.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let fixtures = Fixtures::new();
+    let path = fixtures.path("mapped_truncated.txt");
+    let contents: Vec<u8> = (0..WINDOW_SIZE as u64 + MMAP_THRESHOLD + 3).map(|i| b"crab\n"[i as usize % 5]).collect();
+    // The new end is in the second window: past a page that faults, or in the last page,
+    // which does not fault but reads as zeros past the new end.
+    for new_len in [WINDOW_SIZE as u64 + 100, contents.len() as u64 - 2] {
+        std::fs::write(&path, &contents).unwrap();
+        let mapped = MappedFile::map(File::open(&path).unwrap()).ok().unwrap();
+        let file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+        let mut writer = Truncating { file, len: new_len, output: vec![] };
+        assert!(mapped.write_to(&mut writer).unwrap());
+        assert!(writer.output == contents[..new_len as usize], "wrong output when truncated to {}", new_len);
+    }
+}
+#[test]
+fn many_small_files() {
+    let fixtures = Fixtures::new();
+    let files: Vec<String> = (0..100)
+        .map(|i| {
+            let name = format!("{}.txt", i);
+            std::fs::write(fixtures.path(&name), "crab\n\n\n\tcat\n".repeat(i)).unwrap();
+            name
+        })
+        .collect();
+    let cat_out = fixtures.cat().arg("-s").args(&files).output().unwrap();
+    fixtures.crab()
+        .arg("-s")
+        .args(&files)
+        .assert()
+        .success()
+        .stdout(predicate::eq(cat_out.stdout.as_slice()));
+}
+#[test]
+fn output_format_jsonl() {
+    let fixtures = Fixtures::new();
+    std::fs::write(fixtures.path("a.txt"), b"a\tb\r\n\"q\"\n\xff(\n\nlast").unwrap();
+    std::fs::write(fixtures.path("b.txt"), "crab\n").unwrap();
+    fixtures.crab()
+        .args(["--output-format=jsonl", "a.txt", "missing.txt", "b.txt"])
+        .assert()
+        .failure()
+        .stdout(concat!(
+            "{\"source\":\"a.txt\",\"line\":1,\"offset\":0,\"text\":\"a\\tb\",\"ending\":\"crlf\"}\n",
+            "{\"source\":\"a.txt\",\"line\":2,\"offset\":5,\"text\":\"\\\"q\\\"\",\"ending\":\"lf\"}\n",
+            "{\"source\":\"a.txt\",\"line\":3,\"offset\":9,\"text\":\"\u{fffd}(\",\"text_base64\":\"/yg=\",\"ending\":\"lf\"}\n",
+            "{\"source\":\"a.txt\",\"line\":4,\"offset\":12,\"text\":\"\",\"ending\":\"lf\"}\n",
+            "{\"source\":\"a.txt\",\"line\":5,\"offset\":13,\"text\":\"last\",\"ending\":\"none\"}\n",
+            "{\"source\":\"b.txt\",\"line\":1,\"offset\":0,\"text\":\"crab\",\"ending\":\"lf\"}\n",
+        ))
+        .stderr(predicate::str::contains("missing.txt: No such file or directory"));
+}
+#[test]
+fn output_format_json() {
+    let fixtures = Fixtures::new();
+    fixtures.crab()
+        .args(["--output-format", "json", "-"])
+        .write_stdin("crab\ncat")
+        .assert()
+        .success()
+        .stdout(concat!(
+            "[\n",
+            "{\"source\":\"-\",\"line\":1,\"offset\":0,\"text\":\"crab\",\"ending\":\"lf\"},\n",
+            "{\"source\":\"-\",\"line\":2,\"offset\":5,\"text\":\"cat\",\"ending\":\"none\"}\n",
+            "]\n",
+        ));
+    fixtures.crab()
+        .args(["--output-format", "json"])
+        .write_stdin("")
+        .assert()
+        .success()
+        .stdout("[]\n");
+}
+#[test]
+fn error_format_json() {
+    let fixtures = Fixtures::new();
+    std::fs::create_dir(fixtures.path("dir")).unwrap();
+    fixtures.crab()
+        .args(["--error-format=json", "dir", "missing.txt"])
+        .assert()
+        .failure()
+        .stdout("")
+        .stderr(concat!(
+            "{\"kind\":\"is-directory\",\"path\":\"dir\",\"errno\":21,\"message\":\"Is a directory\"}\n",
+            "{\"kind\":\"open\",\"path\":\"missing.txt\",\"errno\":2,\"message\":\"No such file or directory\"}\n",
+        ));
+}
+#[cfg(unix)]
+#[test]
+fn errors_same_as_cat() {
+    let fixtures = Fixtures::new();
+    std::fs::create_dir(fixtures.path("dir")).unwrap();
+    let cat_out = fixtures.cat().args(["dir", "missing.txt"]).output().unwrap();
+    let cat_err = String::from_utf8(cat_out.stderr).unwrap().replace("cat: ", "crab: ");
+    fixtures.crab()
+        .args(["dir", "missing.txt"])
+        .assert()
+        .failure()
+        .stderr(cat_err);
+}
+#[test]
+fn on_error_abort() {
+    let fixtures = Fixtures::new();
+    std::fs::write(fixtures.path("a.txt"), "crab\n").unwrap();
+    fixtures.crab()
+        .args(["--on-error=abort", "a.txt", "missing.txt", "a.txt"])
+        .assert()
+        .failure()
+        .stdout("crab\n")
+        .stderr("crab: missing.txt: No such file or directory\ncrab: 1 input failed:\n  missing.txt: No such file or directory\n");
+}
+#[test]
+fn on_error_ignore() {
+    let fixtures = Fixtures::new();
+    std::fs::write(fixtures.path("a.txt"), "crab\n").unwrap();
+    std::fs::create_dir(fixtures.path("dir")).unwrap();
+    fixtures.crab()
+        .args(["--on-error=ignore", "-n", "a.txt", "missing.txt", "dir", "a.txt"])
+        .assert()
+        .success()
+        .stdout("     1\tcrab\n     2\tcrab\n")
+        .stderr("");
+}
+#[test]
+fn missing_ok() {
+    let fixtures = Fixtures::new();
+    std::fs::write(fixtures.path("a.txt"), "crab\n").unwrap();
+    std::fs::create_dir(fixtures.path("dir")).unwrap();
+    fixtures.crab()
+        .args(["--missing-ok", "a.txt", "missing.txt", "a.txt"])
+        .assert()
+        .success()
+        .stdout("crab\ncrab\n")
+        .stderr("");
+    // Other failures still count.
+    fixtures.crab()
+        .args(["--missing-ok", "--on-error=continue", "missing.txt", "dir", "a.txt"])
+        .assert()
+        .failure()
+        .stdout("crab\n")
+        .stderr("crab: dir: Is a directory\ncrab: 1 input failed:\n  dir: Is a directory\n");
+}
+#[test]
+fn output_to_file() {
+    let fixtures = Fixtures::new();
+    std::fs::write(fixtures.path("a.txt"), "crab\n").unwrap();
+    std::fs::write(fixtures.path("b.txt"), "cat\n").unwrap();
+    fixtures.crab()
+        .args(["-o", "out.txt", "a.txt", "b.txt"])
+        .assert()
+        .success()
+        .stdout("");
+    fixtures.crab()
+        .args(["--output", "out.txt", "--append", "-n", "a.txt"])
+        .assert()
+        .success();
+    assert_eq!(std::fs::read_to_string(fixtures.path("out.txt")).unwrap(), "crab\ncat\n     1\tcrab\n");
+    // Appending a file to itself would never end.
+    fixtures.crab()
+        .args(["-o", "out.txt", "--append", "out.txt"])
+        .assert()
+        .failure()
+        .stderr("crab: out.txt: input file is output file\n");
+}
+#[test]
+fn output_is_input() {
+    let fixtures = Fixtures::new();
+    std::fs::write(fixtures.path("a.txt"), "crab\n").unwrap();
+    std::fs::write(fixtures.path("b.txt"), "cat\n").unwrap();
+    fixtures.crab()
+        .args(["-o", "a.txt", "-n", "a.txt", "b.txt", "a.txt"])
+        .assert()
+        .success();
+    assert_eq!(
+        std::fs::read_to_string(fixtures.path("a.txt")).unwrap(),
+        "     1\tcrab\n     2\tcat\n     3\tcrab\n"
+    );
+    // No temporary file is left behind.
+    assert_eq!(std::fs::read_dir(fixtures.dir()).unwrap().count(), 2);
+    // Names read from --files-from are only known once the output is open, and --tee files
+    // are kept the same way.
+    std::fs::write(fixtures.path("list.txt"), "b.txt\na.txt\n").unwrap();
+    fixtures.crab()
+        .args(["-o", "a.txt", "--tee", "b.txt", "--files-from", "list.txt"])
+        .assert()
+        .success();
+    let expected = "cat\n     1\tcrab\n     2\tcat\n     3\tcrab\n";
+    assert_eq!(std::fs::read_to_string(fixtures.path("a.txt")).unwrap(), expected);
+    assert_eq!(std::fs::read_to_string(fixtures.path("b.txt")).unwrap(), expected);
+    assert_eq!(std::fs::read_dir(fixtures.dir()).unwrap().count(), 3);
+}
+#[cfg(unix)]
+#[test]
+fn output_atomic() {
+    use std::os::unix::fs::PermissionsExt;
+    let fixtures = Fixtures::new();
+    std::fs::write(fixtures.path("a.txt"), "crab\n").unwrap();
+    std::fs::write(fixtures.path("out.txt"), "old\n").unwrap();
+    std::fs::set_permissions(fixtures.path("out.txt"), std::fs::Permissions::from_mode(0o640)).unwrap();
+    fixtures.crab()
+        .args(["-o", "out.txt", "--atomic", "a.txt", "a.txt"])
+        .assert()
+        .success();
+    let meta = std::fs::metadata(fixtures.path("out.txt")).unwrap();
+    assert_eq!(meta.permissions().mode() & 0o777, 0o640);
+    assert_eq!(std::fs::read_to_string(fixtures.path("out.txt")).unwrap(), "crab\ncrab\n");
+    assert_eq!(std::fs::read_dir(fixtures.dir()).unwrap().count(), 2);
+}
+#[test]
+fn tee_with_own_flags() {
+    let fixtures = Fixtures::new();
+    std::fs::write(fixtures.path("a.txt"), "crab\tcat\n\n\nend\n").unwrap();
+    fixtures.crab()
+        .args(["-n", "--tee", "-:plain.txt", "--tee=-sE:ends.txt", "--tee", "same.txt", "a.txt"])
+        .assert()
+        .success()
+        .stdout("     1\tcrab\tcat\n     2\t\n     3\t\n     4\tend\n");
+    let read = |name| std::fs::read_to_string(fixtures.path(name)).unwrap();
+    assert_eq!(read("plain.txt"), "crab\tcat\n\n\nend\n");
+    assert_eq!(read("ends.txt"), "crab\tcat$\n$\nend$\n");
+    assert_eq!(read("same.txt"), "     1\tcrab\tcat\n     2\t\n     3\t\n     4\tend\n");
+}
+#[test]
+fn tee_invalid_flags() {
+    Fixtures::new()
+        .crab()
+        .args(["--tee", "-nq:out.txt"])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("unknown flag 'q'"));
+}
+#[test]
+fn archive_members() {
+    let fixtures = Fixtures::new();
+    let file = std::fs::File::create(fixtures.path("bundle.tar.gz")).unwrap();
+    let mut tar = tar::Builder::new(flate2::write::GzEncoder::new(file, flate2::Compression::default()));
+    for (name, data) in [("./etc/readme.txt", "skip me\n"), ("./etc/config.yaml", "crab: true\n")] {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        tar.append_data(&mut header, name, data.as_bytes()).unwrap();
+    }
+    tar.into_inner().unwrap().finish().unwrap();
+
+    let mut zip = zip::ZipWriter::new(std::fs::File::create(fixtures.path("release.zip")).unwrap());
+    let options = zip::write::SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+    zip.add_directory("docs/", options).unwrap();
+    zip.start_file("docs/README.md", options).unwrap();
+    zip.write_all(b"# crab\n").unwrap();
+    zip.finish().unwrap();
+
+    fixtures.crab()
+        .args(["-n", "bundle.tar.gz!etc/config.yaml", "release.zip!docs/README.md"])
+        .assert()
+        .success()
+        .stdout("     1\tcrab: true\n     2\t# crab\n");
+    fixtures.crab()
+        .args(["--list", "bundle.tar.gz", "release.zip"])
+        .assert()
+        .success()
+        .stdout("etc/readme.txt\netc/config.yaml\ndocs/\ndocs/README.md\n");
+    fixtures.crab()
+        .args(["bundle.tar.gz!etc/nope", "release.zip!docs"])
+        .assert()
+        .failure()
+        .stderr("crab: bundle.tar.gz!etc/nope: No such member in archive\ncrab: release.zip!docs: Is a directory\n");
+}
+#[cfg(unix)]
+#[test]
+fn unix_socket() {
+    use std::os::unix::net::UnixListener;
+    let fixtures = Fixtures::new();
+    let listener = UnixListener::bind(fixtures.path("diag.sock")).unwrap();
+    let server = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        stream.write_all(b"ok\nready\n").unwrap();
+    });
+    fixtures.crab()
+        .args(["-n", "unix:diag.sock"])
+        .assert()
+        .success()
+        .stdout("     1\tok\n     2\tready\n");
+    server.join().unwrap();
+}
+#[cfg(unix)]
+#[test]
+fn fifo_timeout() {
+    let fixtures = Fixtures::new();
+    std::fs::write(fixtures.path("a.txt"), "crab\n").unwrap();
+    Command::new("mkfifo").arg(fixtures.path("pipe")).status().unwrap();
+    fixtures.crab()
+        .args(["--fifo-timeout", "0.2", "pipe", "a.txt"])
+        .assert()
         .failure()
-        .stdout(predicate::eq(cat_res.stdout.as_slice()))
-        .stderr(predicate::str::contains(
-            " test_inputs/no_permission.txt: Permission denied",
+        .stdout("crab\n")
+        .stderr("crab: pipe: no writer opened the FIFO after 0.2 seconds\n");
+    // A writer that is slow to write is waited for once it has opened the FIFO.
+    let mut writer = Command::new("sh")
+        .args(["-c", "exec 3>pipe; sleep 1; echo late >&3"])
+        .current_dir(fixtures.dir())
+        .spawn()
+        .unwrap();
+    fixtures.crab()
+        .args(["--fifo-timeout", "0.5", "pipe", "a.txt"])
+        .assert()
+        .success()
+        .stdout("late\ncrab\n");
+    writer.wait().unwrap();
+    // With a timeout of 0, a writer that is slow to open the FIFO is waited for too.
+    let mut writer = Command::new("sh")
+        .args(["-c", "sleep 1; echo late >pipe"])
+        .current_dir(fixtures.dir())
+        .spawn()
+        .unwrap();
+    fixtures.crab()
+        .args(["--fifo-timeout", "0", "pipe", "a.txt"])
+        .assert()
+        .success()
+        .stdout("late\ncrab\n");
+    writer.wait().unwrap();
+}
+#[test]
+fn http_url() {
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+    // Answer three requests: a redirect, the page it points to, and a missing page.
+    let server = std::thread::spawn(move || {
+        for _ in 0..3 {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut request = String::new();
+            reader.read_line(&mut request).unwrap();
+            let mut header = String::new();
+            while reader.read_line(&mut header).unwrap() > 2 {
+                header.clear();
+            }
+            let (status, body) = match request.split(' ').nth(1).unwrap() {
+                "/old" => ("301 Moved Permanently\r\nLocation: /log", ""),
+                "/log" => ("200 OK", "remote\nlog"),
+                _ => ("404 Not Found", ""),
+            };
+            let response = format!("HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", status, body.len(), body);
+            reader.into_inner().write_all(response.as_bytes()).unwrap();
+        }
+    });
+    let fixtures = Fixtures::new();
+    std::fs::write(fixtures.path("a.txt"), "\nlocal\n").unwrap();
+    fixtures.crab()
+        .args(["-n", &format!("{}/old", base), "a.txt", &format!("{}/missing", base)])
+        .assert()
+        .failure()
+        .stdout("     1\tremote\n     2\tlog\n     3\tlocal\n")
+        .stderr(format!("crab: {}/missing: HTTP status 404 Not Found\n", base));
+    server.join().unwrap();
+}
+#[test]
+fn http_idle_timeout() {
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+    use std::thread::sleep;
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+    // Answer two requests: a body sent slowly but steadily, and one that stops halfway.
+    let server = std::thread::spawn(move || {
+        for _ in 0..2 {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut request = String::new();
+            reader.read_line(&mut request).unwrap();
+            let mut header = String::new();
+            while reader.read_line(&mut header).unwrap() > 2 {
+                header.clear();
+            }
+            let mut stream = reader.into_inner();
+            if request.contains("/slow") {
+                stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\nConnection: close\r\n\r\n").unwrap();
+                for byte in b"slow\n" {
+                    sleep(Duration::from_millis(300));
+                    stream.write_all(&[*byte]).unwrap();
+                }
+            } else {
+                stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 100\r\nConnection: close\r\n\r\nhalf\n").unwrap();
+                sleep(Duration::from_secs(2));
+            }
+        }
+    });
+    let fixtures = Fixtures::new();
+    // The whole download takes longer than the timeout, but no wait for more data does.
+    fixtures.crab()
+        .args(["--http-timeout", "0.8", &format!("{}/slow", base), &format!("{}/stalled", base)])
+        .assert()
+        .failure()
+        .stdout("slow\nhalf\n")
+        .stderr(format!("crab: {}/stalled: Connection timed out\n", base));
+    server.join().unwrap();
+}
+#[cfg(unix)]
+#[test]
+fn file_descriptors() {
+    use std::io::Seek;
+    let fixtures = Fixtures::new();
+    std::fs::write(fixtures.path("a.txt"), "skip\nread\n").unwrap();
+    // Descriptors are read from where they stand, not from the start of the file.
+    let mut stdin = File::open(fixtures.path("a.txt")).unwrap();
+    stdin.seek(std::io::SeekFrom::Start(5)).unwrap();
+    Command::cargo_bin("crab").unwrap()
+        .args(["-n", "/dev/stdin", "fd:0"])
+        .stdin(stdin)
+        .assert()
+        .success()
+        .stdout("     1\tread\n");
+    let crab = assert_cmd::cargo::cargo_bin("crab");
+    Command::new("sh")
+        .args(["-c", "exec 3< a.txt; \"$0\" fd:3 /dev/fd/3 fd:9", crab.to_str().unwrap()])
+        .current_dir(fixtures.dir())
+        .assert()
+        .failure()
+        .stdout("skip\nread\n")
+        .stderr("crab: fd:9: Bad file descriptor\n");
+    // A file that is named like a descriptor is read as a file.
+    std::fs::write(fixtures.path("fd:3"), "file\n").unwrap();
+    Command::new("sh")
+        .args(["-c", "exec 3< a.txt; \"$0\" fd:3", crab.to_str().unwrap()])
+        .current_dir(fixtures.dir())
+        .assert()
+        .success()
+        .stdout("file\n");
+}
+#[test]
+fn encode_decode() {
+    let fixtures = Fixtures::new();
+    std::fs::write(fixtures.path("a.txt"), "foo").unwrap();
+    std::fs::write(fixtures.path("b.txt"), "bar").unwrap();
+    let encode = |encoding: &str, width: &str| {
+        let output = fixtures.crab().args(["--encode", encoding, "--encode-width", width, "a.txt", "b.txt"]).output().unwrap();
+        String::from_utf8(output.stdout).unwrap()
+    };
+    assert_eq!(encode("base64", "76"), "Zm9vYmFy\n");
+    assert_eq!(encode("base64", "3"), "Zm9\nvYm\nFy\n");
+    assert_eq!(encode("base32", "76"), "MZXW6YTBOI======\n");
+    assert_eq!(encode("hex", "0"), "666f6f626172");
+    fixtures.crab()
+        .args(["--decode", "base64", "-n"])
+        .write_stdin("Zm9v\nYmFy\nCg==\n")
+        .assert()
+        .success()
+        .stdout("     1\tfoobar\n");
+    fixtures.crab()
+        .args(["--encode", "url"])
+        .write_stdin("a b/c~")
+        .assert()
+        .success()
+        .stdout("a%20b%2Fc~");
+    // Url input keeps its whitespace, which stands for itself.
+    fixtures.crab()
+        .args(["--decode", "url"])
+        .write_stdin("a%20b\nc%2Fd e\n")
+        .assert()
+        .success()
+        .stdout("a b\nc/d e\n");
+    fixtures.crab()
+        .args(["--decode", "hex"])
+        .write_stdin("666f6x")
+        .assert()
+        .failure()
+        .stdout("fo")
+        .stderr("crab: invalid hex input at byte 5\n");
+}
+#[test]
+fn checksums() {
+    let fixtures = Fixtures::new();
+    std::fs::write(fixtures.path("a.txt"), "crab\n").unwrap();
+    std::fs::write(fixtures.path("b.txt"), "cat\n").unwrap();
+    fixtures.crab()
+        .args(["--checksum", "sha256", "a.txt", "-"])
+        .write_stdin("cat\n")
+        .assert()
+        .success()
+        .stdout("crab\ncat\n")
+        .stderr(concat!(
+            "7826b708c666008d075990de861a09e4de584ab032239151768c26150290a386  a.txt\n",
+            "175cc6f362b2f75acd08a373e000144fdb8d14a833d4b70fd743f16a7039103f  -\n",
+            "63d0dd722758c57655ed52c7d25b92fd8eeb74f544f25fb74b39b7398b0198c7  (output)\n",
+        ));
+    // The inputs are checksummed as they are read, and the output as it is written.
+    fixtures.crab()
+        .args(["-n", "--checksum", "crc32", "--checksum-file", "sums.txt", "-o", "out.txt", "a.txt", "b.txt"])
+        .assert()
+        .success()
+        .stderr("");
+    assert_eq!(
+        std::fs::read_to_string(fixtures.path("sums.txt")).unwrap(),
+        "278dd3bb  a.txt\nea44730a  b.txt\n772be900  out.txt\n"
+    );
+    // The sha256 lines can be checked again with sha256sum.
+    fixtures.crab()
+        .args(["--checksum", "sha256", "--checksum-file", "sums.txt", "-o", "out.txt", "a.txt", "b.txt"])
+        .assert()
+        .success();
+    Command::new("sha256sum")
+        .args(["-c", "--quiet", "sums.txt"])
+        .current_dir(fixtures.dir())
+        .assert()
+        .success();
+}
+#[cfg(unix)]
+#[test]
+fn checksum_escaped_names() {
+    let fixtures = Fixtures::new();
+    std::fs::write(fixtures.path("new\nline.txt"), "crab\n").unwrap();
+    std::fs::write(fixtures.path("back\\slash.txt"), "cat\n").unwrap();
+    // Names with a newline or a backslash are escaped, and their lines start with a backslash.
+    fixtures.crab()
+        .args(["--checksum", "crc32", "--checksum-file", "sums.txt", "-o", "out.txt", "new\nline.txt", "back\\slash.txt"])
+        .assert()
+        .success();
+    assert_eq!(
+        std::fs::read_to_string(fixtures.path("sums.txt")).unwrap(),
+        "\\278dd3bb  new\\nline.txt\n\\ea44730a  back\\\\slash.txt\n84df6295  out.txt\n"
+    );
+    // So sha256sum reads the names back.
+    fixtures.crab()
+        .args(["--checksum", "sha256", "--checksum-file", "sums.txt", "-o", "out.txt", "new\nline.txt", "back\\slash.txt"])
+        .assert()
+        .success();
+    Command::new("sha256sum")
+        .args(["-c", "--quiet", "sums.txt"])
+        .current_dir(fixtures.dir())
+        .assert()
+        .success();
+}
+#[test]
+fn stats() {
+    let fixtures = Fixtures::new();
+    std::fs::write(fixtures.path("a.txt"), b"crab\tcat\r\n\n\n\n\xc3\xa9\xff").unwrap();
+    std::fs::write(fixtures.path("b.txt"), "one two\n").unwrap();
+    fixtures.crab()
+        .args(["-s", "--stats", "a.txt", "b.txt"])
+        .assert()
+        .success()
+        .stdout(&b"crab\tcat\r\n\n\xc3\xa9\xffone two\n"[..])
+        .stderr(concat!(
+            "lines  words  bytes  longest  nonprint  tabs  crs  squeezed  invalid  source\n",
+            "    4      3     16        9         4     1    1         2        1  a.txt\n",
+            "    1      2      8        7         0     0    0         0        0  b.txt\n",
+            "    5      5     24        9         4     1    1         2        1  total\n",
+        ));
+    // The decoded data is counted, and its empty lines are the ones -s removes.
+    fixtures.crab()
+        .args(["-s", "--stats", "--decode=base64", "-"])
+        .write_stdin("YQoKCmIK\n")
+        .assert()
+        .success()
+        .stdout("a\n\nb\n")
+        .stderr(concat!(
+            "lines  words  bytes  longest  nonprint  tabs  crs  squeezed  invalid  source\n",
+            "    4      2      6        1         0     0    0         1        0  -\n",
         ));
 }
+#[test]
+fn expand_tabs() {
+    let fixtures = Fixtures::new();
+    fixtures.crab()
+        .args(["-n", "--expand-tabs"])
+        .write_stdin("a\tb\n日本\tx\n")
+        .assert()
+        .success()
+        .stdout("     1  a       b\n     2  日本    x\n");
+    fixtures.crab()
+        .args(["-v", "--expand-tabs=3,6"])
+        .write_stdin("\x01\tb\tc\td\n")
+        .assert()
+        .success()
+        .stdout("^A b  c d\n");
+    fixtures.crab()
+        .args(["--unexpand=4"])
+        .write_stdin("ab  c  d     e \n")
+        .assert()
+        .success()
+        .stdout("ab\tc  d\t e \n");
+    // -T shows TABs rather than expanding them.
+    fixtures.crab()
+        .args(["-T", "--expand-tabs"])
+        .write_stdin("a\tb\n")
+        .assert()
+        .success()
+        .stdout("a^Ib\n");
+}
+#[test]
+fn visible_whitespace() {
+    let fixtures = Fixtures::new();
+    fixtures.crab()
+        .args(["--show-spaces"])
+        .write_stdin("a b\tc\u{a0}d\n")
+        .assert()
+        .success()
+        .stdout("a·b→c␣d\n");
+    fixtures.crab()
+        .args(["--show-spaces=ascii", "-E", "--expand-tabs=4"])
+        .write_stdin("a\tb\u{a0}\n")
+        .assert()
+        .success()
+        .stdout("a>  b+$\n");
+    // Only the blanks that end a line are highlighted, also before a CRLF line ending.
+    fixtures.crab()
+        .args(["--highlight-trailing", "--color=always"])
+        .write_stdin("a b \t\r\nc\u{a0}\n")
+        .assert()
+        .success()
+        .stdout("a b\x1b[41m \t\x1b[0m\r\nc\x1b[41m\u{a0}\x1b[0m\n");
+    // The blanks are held back across chunks, so threads format the same way.
+    fixtures.crab()
+        .args(["--highlight-trailing", "--color=always", "--threads", "3"])
+        .write_stdin("a \n \n")
+        .assert()
+        .success()
+        .stdout("a\x1b[41m \x1b[0m\n\x1b[41m \x1b[0m\n");
+    // Output that does not go to a terminal is not colored, unless asked for.
+    fixtures.crab()
+        .args(["--highlight-trailing"])
+        .write_stdin("a \n")
+        .assert()
+        .success()
+        .stdout("a \n");
+}
+#[test]
+fn wrap_and_truncate() {
+    let fixtures = Fixtures::new();
+    std::fs::write(fixtures.path("long.json"), "{\"a\":\"日本語\",\"b\":[1,2]}\nok\n").unwrap();
+    // Wide characters take two columns, and continuation lines get a blank gutter.
+    fixtures.crab()
+        .args(["-n", "--wrap=14", "long.json"])
+        .assert()
+        .success()
+        .stdout("     1\t{\"a\":\"\n        日本語\n        \",\"b\":\n        [1,2]}\n     2\tok\n");
+    // The ^X notation of -v counts as two columns.
+    fixtures.crab()
+        .args(["-v", "--truncate=5", "-"])
+        .write_stdin("\x01\x02\x03\nabc\n")
+        .assert()
+        .success()
+        .stdout("^A^B\nabc\n");
+    // Without a terminal, the automatic width leaves the lines as they are.
+    fixtures.crab()
+        .args(["--wrap", "long.json"])
+        .env_remove("COLUMNS")
+        .assert()
+        .success()
+        .stdout("{\"a\":\"日本語\",\"b\":[1,2]}\nok\n");
+}
+#[test]
+fn pipeline_waits_for_slow_output() {
+    /// An output that holds its first write until all the input is in, or for a while if the
+    /// pipeline stops taking input, and checks how far ahead of it the input got.
+    struct SlowOutput {
+        accepted: Arc<AtomicUsize>,
+        total: usize,
+        written: usize,
+        ahead: Arc<AtomicUsize>,
+    }
+    impl Write for SlowOutput {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            let start = std::time::Instant::now();
+            while self.written == 0
+                && self.accepted.load(Ordering::SeqCst) < self.total
+                && start.elapsed() < Duration::from_secs(2)
+            {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            let ahead = self.accepted.load(Ordering::SeqCst).saturating_sub(self.written);
+            self.ahead.fetch_max(ahead, Ordering::SeqCst);
+            self.written += buf.len();
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let (threads, chunks) = (2, 24);
+    let (accepted, ahead) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+    let output = SlowOutput {
+        accepted: Arc::clone(&accepted),
+        total: chunks * CHUNK_SIZE,
+        written: 0,
+        ahead: Arc::clone(&ahead),
+    };
+    let options = FormatOptions { show_ends: true, ..FormatOptions::default() };
+    let mut pipeline = Pipeline::new(threads, options, Box::new(output));
+    let chunk: Vec<u8> = b"crab\n".iter().copied().cycle().take(CHUNK_SIZE).collect();
+    for _ in 0..chunks {
+        pipeline.write_all(&chunk).unwrap();
+        accepted.fetch_add(chunk.len(), Ordering::SeqCst);
+    }
+    pipeline.flush().unwrap();
+    drop(pipeline);
+    // Only a few chunks per worker may be handed out before the output has written them.
+    let ahead = ahead.load(Ordering::SeqCst);
+    assert!(ahead <= (threads * 4 + 1) * CHUNK_SIZE, "the input got {} bytes ahead", ahead);
+}
@@ -0,0 +1,276 @@
+use std::io::Write;
+use unicode_width::UnicodeWidthChar;
+
+/// What happens to the part of a line that does not fit in the width.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Overflow {
+    /// It goes on the next lines, as many as it takes.
+    Wrap,
+    /// It is left out.
+    Truncate,
+}
+
+/// The width of the gutter of numbered lines, six digits and a TAB.
+const GUTTER: usize = 8;
+/// The escape sequence that resets the colors, as written by `--highlight-trailing`.
+const RESET: &[u8] = b"\x1b[0m";
+
+/// Returns the number of columns of the terminal, from the `COLUMNS` environment variable like
+/// `ls`, or from the terminal standard output is on. Returns `None` if neither says.
+pub fn terminal_width() -> Option<usize> {
+    let columns = std::env::var("COLUMNS").ok().and_then(|columns| columns.parse().ok());
+    columns.filter(|columns| *columns > 0).or_else(window_width)
+}
+
+/// Returns the number of columns of the terminal standard output is on, if it is on one.
+#[cfg(unix)]
+fn window_width() -> Option<usize> {
+    // SAFETY: `size` is a valid `winsize` for the ioctl to fill in.
+    unsafe {
+        let mut size: libc::winsize = std::mem::zeroed();
+        let ok = libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) == 0;
+        (ok && size.ws_col > 0).then_some(size.ws_col as usize)
+    }
+}
+
+/// Returns the number of columns of the terminal standard output is on, if it is on one.
+#[cfg(not(unix))]
+fn window_width() -> Option<usize> {
+    None
+}
+
+/// The state of the fitting of formatted lines in a width, carried from one write to the next.
+///
+/// Widths are measured on the output as it appears on a terminal: characters take their
+/// Unicode display width, so wide characters count twice and combining marks not at all,
+/// the `^X` and `M-` notation of `-v` counts as the characters it is made of, TABs reach the
+/// next multiple of 8 columns, and escape sequences take no room.
+pub struct WrapState {
+    /// The number of columns lines are fitted in.
+    width: usize,
+    /// What happens to the part of a line that does not fit.
+    overflow: Overflow,
+    /// Whether lines are numbered, so continuation lines start with a blank gutter in place of
+    /// the number.
+    gutter: bool,
+    /// Whether the output uses the `^X` and `M-` notation of `-v`, which is not split.
+    notation: bool,
+    /// The column the output has reached on the current line.
+    column: usize,
+    /// Whether the rest of the current line is left out.
+    cut: bool,
+    /// The escape sequence being read, if one was started.
+    escape: Vec<u8>,
+    /// The last escape sequence setting colors, if they were not reset since. It is set again
+    /// after the break of a wrapped line.
+    style: Vec<u8>,
+    /// The start of a `^X` or `M-` notation, held back until it is complete. A `^` or an `M`
+    /// from the input itself is held back the same way, which only keeps it with the next
+    /// character.
+    unit: Vec<u8>,
+    /// The bytes of a UTF-8 character held back until its width is known.
+    pending: Vec<u8>,
+    /// Whether the start of the current UTF-8 character was written by `flush`.
+    released: bool,
+    /// The number of continuation bytes still expected for the current UTF-8 character.
+    needed: u8,
+    /// The bits of the current UTF-8 character seen so far.
+    code: u32,
+}
+
+impl WrapState {
+    /// Creates the state at the start of the output, fitting lines in `width` columns. If
+    /// `gutter` is set, lines start with the gutter of `-n`, and if `notation` is set, the
+    /// output uses the notation of `-v`.
+    pub fn new(width: usize, overflow: Overflow, gutter: bool, notation: bool) -> Self {
+        WrapState {
+            width,
+            overflow,
+            gutter,
+            notation,
+            column: 0,
+            cut: false,
+            escape: vec![],
+            style: vec![],
+            unit: vec![],
+            pending: vec![],
+            released: false,
+            needed: 0,
+            code: 0,
+        }
+    }
+
+    /// Fits the formatted output in `buf` in the width and writes it to `writer`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `std::io::Error` if there is a problem writing to `writer`.
+    pub fn format<W: Write>(&mut self, buf: &[u8], writer: &mut W) -> std::io::Result<()> {
+        let mut out = Vec::with_capacity(buf.len() + buf.len() / 16);
+        for byte in buf {
+            self.advance(*byte, &mut out);
+        }
+        writer.write_all(&out)
+    }
+
+    /// Writes the start of a UTF-8 character held back by the state, since what was written so
+    /// far has to reach the output now. The width of the character is still counted once it is
+    /// complete.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `std::io::Error` if there is a problem writing to `writer`.
+    pub fn flush<W: Write>(&mut self, writer: &mut W) -> std::io::Result<()> {
+        if !self.unit.is_empty() {
+            let (unit, mut out) = (std::mem::take(&mut self.unit), vec![]);
+            self.place(&unit, unit.len(), &mut out);
+            writer.write_all(&out)?;
+        }
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        self.released = true;
+        let pending = std::mem::take(&mut self.pending);
+        if self.cut {
+            return Ok(());
+        }
+        writer.write_all(&pending)
+    }
+
+    /// Moves the state over one byte of the output, appending what to write to `out`.
+    fn advance(&mut self, byte: u8, out: &mut Vec<u8>) {
+        if !self.escape.is_empty() {
+            return self.escape(byte, out);
+        }
+        if !self.unit.is_empty() && self.unit(byte, out) {
+            return;
+        }
+        if self.needed > 0 {
+            if byte & 0xc0 == 0x80 {
+                self.code = self.code << 6 | (byte & 0x3f) as u32;
+                self.needed -= 1;
+                self.pending.push(byte);
+                if self.needed == 0 {
+                    let width = char::from_u32(self.code).map_or(1, |character| character.width().unwrap_or(0));
+                    self.complete(width, out);
+                }
+                return;
+            }
+            // A character cut short shows as a replacement character
+            self.needed = 0;
+            self.complete(1, out);
+        }
+        let (needed, bits) = match byte {
+            b'\n' => {
+                self.column = 0;
+                self.cut = false;
+                return out.push(byte);
+            }
+            b'\t' => {
+                let width = GUTTER - self.column % GUTTER;
+                return self.place(&[byte], width, out);
+            }
+            0x1b => {
+                self.escape.push(byte);
+                return out.push(byte);
+            }
+            0..=31 | 127 => return out.push(byte),
+            b'^' | b'M' if self.notation => return self.unit.push(byte),
+            32..=126 => return self.place(&[byte], 1, out),
+            0xc2..=0xdf => (1, byte & 0x1f),
+            0xe0..=0xef => (2, byte & 0x0f),
+            0xf0..=0xf4 => (3, byte & 0x07),
+            _ => return self.place(&[byte], 1, out),
+        };
+        self.needed = needed;
+        self.code = bits as u32;
+        self.pending.push(byte);
+    }
+
+    /// Places the UTF-8 character held back, once its width is known. If its start was
+    /// already written by `flush`, the rest of it cannot go on another line.
+    fn complete(&mut self, width: usize, out: &mut Vec<u8>) {
+        let character = std::mem::take(&mut self.pending);
+        if std::mem::take(&mut self.released) {
+            if !self.cut {
+                out.extend_from_slice(&character);
+                self.column += width;
+            }
+            return;
+        }
+        self.place(&character, width, out);
+    }
+
+    /// Moves over a byte following the start of a notation. Returns whether the byte is part of
+    /// it; if not, the start is placed on its own and the byte still has to be moved over.
+    fn unit(&mut self, byte: u8, out: &mut Vec<u8>) -> bool {
+        match (self.unit.as_slice(), byte) {
+            ([b'M'], b'-') | ([b'M', b'-'], b'^') => {
+                self.unit.push(byte);
+                true
+            }
+            ([b'^'] | [b'M', b'-', b'^'], 0x3f..=0x5f) | ([b'M', b'-'], 0x20..=0x7e) => {
+                let mut unit = std::mem::take(&mut self.unit);
+                unit.push(byte);
+                self.place(&unit, unit.len(), out);
+                true
+            }
+            _ => {
+                let unit = std::mem::take(&mut self.unit);
+                self.place(&unit, unit.len(), out);
+                false
+            }
+        }
+    }
+
+    /// Moves over a byte of an escape sequence, which takes no room. Sequences that set colors
+    /// are remembered until they are reset.
+    fn escape(&mut self, byte: u8, out: &mut Vec<u8>) {
+        self.escape.push(byte);
+        out.push(byte);
+        let done = match self.escape.len() {
+            2 => byte != b'[',
+            _ => (0x40..=0x7e).contains(&byte),
+        };
+        if !done {
+            return;
+        }
+        let sequence = std::mem::take(&mut self.escape);
+        if sequence.ends_with(b"m") {
+            self.style = if sequence == RESET || sequence == b"\x1b[m" { vec![] } else { sequence };
+        }
+    }
+
+    /// Writes a character that takes `width` columns, on the next line if it does not fit on
+    /// this one, or not at all if the rest of the line is cut.
+    fn place(&mut self, character: &[u8], width: usize, out: &mut Vec<u8>) {
+        if self.cut {
+            return;
+        }
+        // Something has to go on every line, however narrow
+        let start = if self.gutter { GUTTER } else { 0 };
+        if self.column + width > self.width && self.column > start {
+            match self.overflow {
+                Overflow::Truncate => {
+                    self.cut = true;
+                    return;
+                }
+                Overflow::Wrap => {
+                    if !self.style.is_empty() {
+                        out.extend_from_slice(RESET);
+                    }
+                    out.push(b'\n');
+                    if self.gutter {
+                        out.extend_from_slice(&[b' '; GUTTER]);
+                    }
+                    out.extend_from_slice(&self.style);
+                    self.column = start;
+                }
+            }
+        }
+        // A TAB at the start of a continuation line reaches the next multiple of 8 columns.
+        let width = if character == b"\t" { GUTTER - self.column % GUTTER } else { width };
+        out.extend_from_slice(character);
+        self.column += width;
+    }
+}
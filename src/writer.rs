@@ -1,33 +1,78 @@
-use std::io::{BufWriter, stdout, StdoutLock, Write};
-use crate::args::ARGS;
-use crate::mapping::{new_line, tab};
+use std::io::{BufWriter, Write};
+use std::sync::{Arc, Mutex};
+use crate::checksum::{Algorithm, Hasher, Manifest};
+use crate::codec::CodecState;
+use crate::format::{FormatOptions, FormatState};
+use crate::json::JsonState;
+use crate::reader::{InputSource, SourceWriter};
+use crate::stats::StatsState;
+use crate::wrap::WrapState;
 
-/// The OutWriter struct represents a buffered writer for standard output with additional features
+/// The way an `OutWriter` renders its input.
+enum Backend {
+    /// The input itself, with line numbers and special characters as configured.
+    Text(FormatState),
+    /// A JSON object for every line of the input.
+    Json(JsonState),
+}
+
+/// The OutWriter struct represents a buffered writer for the output with additional features
 /// such as line numbering and showing non-printing characters.
-pub struct OutWriter {
-    /// The underlying buffer writer for standard output.
-    writer: BufWriter<StdoutLock<'static>>,
-    /// An array of size 2 that stores the last two characters written to the output stream.
-    last_two_chars: [u8; 2],
-    /// A counter for the number of lines written to the output stream.
-    lines_count: usize,
-    /// A flag that indicates whether the last line written to the output stream was empty.
-    last_line_empty: bool
-}
-
-impl OutWriter {
-    /// Creates a new instance of `OutWriter`.
-    pub fn new() -> Self {
+pub struct OutWriter<W: Write> {
+    /// The underlying buffer writer for the output.
+    writer: BufWriter<W>,
+    /// The rendering state carried from one write to the next.
+    backend: Backend,
+}
+
+impl<W: Write> OutWriter<W> {
+    /// Creates a new instance of `OutWriter` writing to `writer`, formatting with the given flags.
+    pub fn new(writer: W, options: FormatOptions) -> Self {
         Self {
-            writer: BufWriter::new(stdout().lock()),
-            last_two_chars: [0,0],
-            lines_count: 1,
-            last_line_empty: false,
+            writer: BufWriter::new(writer),
+            backend: Backend::Text(FormatState::new(options)),
+        }
+    }
+
+    /// Creates a new instance of `OutWriter` writing to `writer` that describes every line as
+    /// a JSON object, all of them in a single array if `array` is set, or one per output line
+    /// otherwise.
+    pub fn json(writer: W, array: bool) -> Self {
+        Self {
+            writer: BufWriter::new(writer),
+            backend: Backend::Json(JsonState::new(array)),
         }
     }
 }
 
-impl Write for OutWriter {
+impl<W: Write> SourceWriter for OutWriter<W> {
+    fn start_source(&mut self, source: &InputSource) -> std::io::Result<()> {
+        if let Backend::Json(state) = &mut self.backend {
+            state.start_source(source);
+        }
+        Ok(())
+    }
+
+    /// Text lines carry on from one source to the next, like in GNU cat, while JSON lines
+    /// end with their source.
+    fn end_source(&mut self) -> std::io::Result<()> {
+        match &mut self.backend {
+            Backend::Text(_) => Ok(()),
+            Backend::Json(state) => state.end_source(&mut self.writer),
+        }
+    }
+
+    /// Writes whatever the formatting still holds back at the end of the input, and flushes.
+    fn finish(&mut self) -> std::io::Result<()> {
+        match &mut self.backend {
+            Backend::Text(state) => state.finish(&mut self.writer)?,
+            Backend::Json(state) => state.finish(&mut self.writer)?,
+        }
+        self.writer.flush()
+    }
+}
+
+impl<W: Write> Write for OutWriter<W> {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
         match self.write_all(buf) {
             Ok(_) => Ok(buf.len()),
@@ -37,10 +82,8 @@ impl Write for OutWriter {
 
     fn flush(&mut self) -> std::io::Result<()> { self.writer.flush() }
 
-    /// Write all bytes in the given buffer to the underlying writer. Special characters are handled based
-    /// on the flags provided in the `ARGS` global variable. If the `squeeze_blank` flag is set, empty lines
-    /// are skipped if the last line and the current line are both empty. If the `number_lines` flag is set,
-    /// lines are numbered, and if the `number_non_blank` flag is set, only non-empty lines are numbered.
+    /// Write all bytes in the given buffer to the underlying writer, rendered according to
+    /// the backend the writer was created with. See `FormatState::format` and `JsonState::format`.
     ///
     /// # Arguments
     ///
@@ -50,41 +93,271 @@ impl Write for OutWriter {
     ///
     /// Returns an `std::io::Error` if there is a problem writing to the underlying writer.
     fn write_all(&mut self, buf: &[u8]) -> std::io::Result<()> {
-        for character in buf {
-            // Check if the current line is empty
-            let is_empty = matches!(
-                (character, self.last_two_chars),
-                (b'\n', [b'\n', b'\r']) | (b'\n', [_, b'\n']) | (b'\n', [0, 0])
-            );
-            // If the `squeeze_blank` flag is set and the last line was empty and the current line is empty, skip this character
-            if ARGS.squeeze_blank && self.last_line_empty && is_empty {
-                continue
-            }
-            self.last_line_empty = is_empty;
-
-            // If the `number_lines` flag is set and the current line should be numbered, output the line number
-            if ARGS.number_lines && !(ARGS.number_non_blank && is_empty) && (self.last_two_chars[1] == b'\n' || matches!(self.last_two_chars, [0,0])) {
-                write!(self.writer, "{:>6}\t", self.lines_count)?;
-                self.lines_count += 1;
-            }
-
-            // Output the current character according to the settings and any special character handling
-            match character {
-                b'\t' => self.writer.write_all(tab()),
-                b'\n' => self.writer.write_all(new_line()),
-                _ if !ARGS.show_non_printing => self.writer.write_all(&[*character]),
-                0..=8 | 11..=31 => self.writer.write_all(&[b'^', character + 64]),
-                127 => self.writer.write_all(b"^?"),
-                128..=159 => self.writer.write_all(&[b'M', b'-', b'^', character - 64]),
-                160..=254 => self.writer.write_all(&[b'M', b'-', character - 128]),
-                255 => self.writer.write_all(b"M-^?"),
-                _ => self.writer.write_all(&[*character])
-            }?;
-
-            // Update the last two characters written with the current character
-            self.last_two_chars[0] = self.last_two_chars[1];
-            self.last_two_chars[1] = *character;
+        match &mut self.backend {
+            Backend::Text(state) => state.format(buf, &mut self.writer),
+            Backend::Json(state) => state.format(buf, &mut self.writer),
         }
+    }
+}
+
+/// A writer that hands everything it receives to several writers, each rendering it to its
+/// own output in its own way.
+pub struct Tee {
+    /// The writers, in the order they were given.
+    writers: Vec<Box<dyn SourceWriter>>,
+}
+
+impl Tee {
+    /// Creates a new `Tee` writing to every one of `writers`.
+    pub fn new(writers: Vec<Box<dyn SourceWriter>>) -> Self {
+        Tee { writers }
+    }
+}
+
+impl SourceWriter for Tee {
+    fn start_source(&mut self, source: &InputSource) -> std::io::Result<()> {
+        self.writers.iter_mut().try_for_each(|writer| writer.start_source(source))
+    }
+
+    fn end_source(&mut self) -> std::io::Result<()> {
+        self.writers.iter_mut().try_for_each(|writer| writer.end_source())
+    }
+
+    fn finish(&mut self) -> std::io::Result<()> {
+        self.writers.iter_mut().try_for_each(|writer| writer.finish())
+    }
+}
+
+impl Write for Tee {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writers.iter_mut().try_for_each(|writer| writer.flush())
+    }
+
+    /// Writes the whole buffer to every writer, stopping at the first one that fails.
+    fn write_all(&mut self, buf: &[u8]) -> std::io::Result<()> {
+        self.writers.iter_mut().try_for_each(|writer| writer.write_all(buf))
+    }
+}
+
+/// A writer that encodes or decodes everything it receives, as a single stream, before
+/// handing it to another writer.
+pub struct Transcoder<W: SourceWriter> {
+    /// The writer receiving the converted stream.
+    writer: W,
+    /// The conversion state carried from one write to the next.
+    codec: CodecState,
+}
+
+impl<W: SourceWriter> Transcoder<W> {
+    /// Creates a new `Transcoder` converting with `codec` and writing to `writer`.
+    pub fn new(writer: W, codec: CodecState) -> Self {
+        Transcoder { writer, codec }
+    }
+}
+
+impl<W: SourceWriter> SourceWriter for Transcoder<W> {
+    fn start_source(&mut self, source: &InputSource) -> std::io::Result<()> {
+        self.writer.start_source(source)
+    }
+
+    fn end_source(&mut self) -> std::io::Result<()> {
+        self.writer.end_source()
+    }
+
+    /// Writes the end of the conversion, then finishes the writer.
+    fn finish(&mut self) -> std::io::Result<()> {
+        self.codec.finish(&mut self.writer)?;
+        self.writer.finish()
+    }
+}
+
+impl<W: SourceWriter> Write for Transcoder<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+
+    /// Converts the whole buffer and writes the result. See `CodecState::format`.
+    fn write_all(&mut self, buf: &[u8]) -> std::io::Result<()> {
+        self.codec.format(buf, &mut self.writer)
+    }
+}
+
+/// A writer that checksums the data of every input source on its way to another writer.
+pub struct Checksums<W: SourceWriter> {
+    /// The writer receiving the data.
+    writer: W,
+    /// The algorithm of the checksums.
+    algorithm: Algorithm,
+    /// The name and the checksum of the source being written, if any.
+    current: Option<(String, Hasher)>,
+    /// The checksums of the sources written so far.
+    pub manifest: Manifest,
+}
+
+impl<W: SourceWriter> Checksums<W> {
+    /// Creates a new `Checksums` computing checksums with `algorithm` and writing to `writer`.
+    pub fn new(writer: W, algorithm: Algorithm) -> Self {
+        Checksums { writer, algorithm, current: None, manifest: Manifest::default() }
+    }
+}
+
+impl<W: SourceWriter> SourceWriter for Checksums<W> {
+    fn start_source(&mut self, source: &InputSource) -> std::io::Result<()> {
+        self.current = Some((source.name().to_string(), Hasher::new(self.algorithm)));
+        self.writer.start_source(source)
+    }
+
+    fn end_source(&mut self) -> std::io::Result<()> {
+        if let Some((name, hasher)) = self.current.take() {
+            self.manifest.push(&name, hasher.finalize());
+        }
+        self.writer.end_source()
+    }
+
+    fn finish(&mut self) -> std::io::Result<()> {
+        self.writer.finish()
+    }
+}
+
+impl<W: SourceWriter> Write for Checksums<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> std::io::Result<()> {
+        if let Some((_, hasher)) = &mut self.current {
+            hasher.update(buf);
+        }
+        self.writer.write_all(buf)
+    }
+}
+
+/// A writer that checksums everything written through it, as it reaches the output. The
+/// checksum is shared, so it can be read once the writer has been handed to another thread.
+pub struct HashWriter {
+    /// The output.
+    writer: Box<dyn Write + Send>,
+    /// The checksum of the data written so far.
+    hasher: Arc<Mutex<Hasher>>,
+}
+
+impl HashWriter {
+    /// Creates a new `HashWriter` writing to `writer` and adding what it writes to `hasher`.
+    pub fn new(writer: Box<dyn Write + Send>, hasher: Arc<Mutex<Hasher>>) -> Self {
+        HashWriter { writer, hasher }
+    }
+}
+
+impl Write for HashWriter {
+    /// Writes to the output, and only adds to the checksum what the output accepted.
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.writer.write(buf)?;
+        self.hasher.lock().unwrap().update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// A writer that counts what is in every input source on its way to another writer, and
+/// prints a table of the counts to standard error output at the end.
+pub struct Stats<W: SourceWriter> {
+    /// The writer receiving the data.
+    writer: W,
+    /// The counts of the sources written so far.
+    state: StatsState,
+}
+
+impl<W: SourceWriter> Stats<W> {
+    /// Creates a new `Stats` writing to `writer`, counting squeezed empty lines if `squeeze`
+    /// is set.
+    pub fn new(writer: W, squeeze: bool) -> Self {
+        Stats { writer, state: StatsState::new(squeeze) }
+    }
+}
+
+impl<W: SourceWriter> SourceWriter for Stats<W> {
+    fn start_source(&mut self, source: &InputSource) -> std::io::Result<()> {
+        self.state.start_source(&source.name());
+        self.writer.start_source(source)
+    }
+
+    fn end_source(&mut self) -> std::io::Result<()> {
+        self.state.end_source();
+        self.writer.end_source()
+    }
+
+    /// Finishes the writer, then prints the table, so it follows all of the output.
+    fn finish(&mut self) -> std::io::Result<()> {
+        self.writer.finish()?;
+        let _ = self.state.write_table(&mut std::io::stderr().lock());
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+impl<W: SourceWriter> Write for Stats<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> std::io::Result<()> {
+        self.state.update(buf);
+        self.writer.write_all(buf)
+    }
+}
+
+/// A writer that fits the formatted lines it receives in a width, wrapping or truncating them,
+/// on their way to the output.
+pub struct Wrapper {
+    /// The output.
+    writer: Box<dyn Write + Send>,
+    /// The fitting state carried from one write to the next.
+    state: WrapState,
+}
+
+impl Wrapper {
+    /// Creates a new `Wrapper` fitting lines as `state` says and writing them to `writer`.
+    pub fn new(writer: Box<dyn Write + Send>, state: WrapState) -> Self {
+        Wrapper { writer, state }
+    }
+}
+
+impl Write for Wrapper {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    /// Writes anything held back, even the start of a character whose width is not known yet.
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.state.flush(&mut self.writer)?;
+        self.writer.flush()
+    }
+
+    /// Fits the whole buffer and writes the result. See `WrapState::format`.
+    fn write_all(&mut self, buf: &[u8]) -> std::io::Result<()> {
+        self.state.format(buf, &mut self.writer)
+    }
+}